@@ -0,0 +1,90 @@
+// Times the editor's own layout code on a large document, and checks that a
+// frame of scrolling fits in the frame budget and costs a small fraction of
+// laying out the whole tree.
+
+use std::time::{Duration, Instant};
+
+use skulpin_test::{BufferLayout, JsonBuffer, JsonInput, JsonNode, JsonNumber, JsonVariant, LayoutMetrics};
+
+const ITEMS: usize = 100_000;
+const FRAMES: usize = 1_000;
+const KEYSTROKES: usize = 100;
+// A frame at 60 frames a second, which scrolling has to fit in.
+const FRAME_BUDGET: Duration = Duration::from_micros(16_667);
+
+fn node(variant: JsonVariant, parent: usize, left: usize, right: usize) -> JsonNode {
+	JsonNode {
+		variant,
+		parent,
+		left,
+		right,
+	}
+}
+
+// An array of ITEMS objects, each with an "id" and a "name": five nodes per
+// item, so half a million nodes in all.
+fn large_buffer() -> JsonBuffer {
+	let items: Vec<usize> = (0..ITEMS).map(|i| 1 + i*5).collect();
+	let mut nodes = vec![node(JsonVariant::Array(items.clone()), 0, 0, 0)];
+	for (i, &item) in items.iter().enumerate() {
+		let left = if i == 0 {0} else {items[i-1]};
+		let right = if i+1 == ITEMS {0} else {items[i+1]};
+		nodes.push(node(JsonVariant::Object(vec![item+1, item+2]), 0, left, right));
+		nodes.push(node(JsonVariant::ObjectEntry("id".to_string(), item+3), item, item, item+2));
+		nodes.push(node(JsonVariant::ObjectEntry("name".to_string(), item+4), item, item+1, item));
+		nodes.push(node(JsonVariant::Number(JsonNumber::from(i as u64)), item+1, item+1, item+1));
+		nodes.push(node(JsonVariant::String(format!("item {}", i)), item+2, item+2, item+2));
+	}
+	JsonBuffer::new(nodes)
+}
+
+fn main() {
+	// The default theme's sizes, with the lines a 600 pixel high window shows
+	// above the status line.
+	let metrics = LayoutMetrics {
+		line_height: 18.,
+		character_width: 9.,
+		indent_width: 2,
+		visible_lines: 32,
+	};
+	let mut layout = BufferLayout::default();
+	let mut buffer = large_buffer();
+
+	// The first frame also builds the line layout of the whole document.
+	let start = Instant::now();
+	buffer.lay_out(&metrics, &mut layout);
+	println!("first layout of {} nodes: {:?}", buffer.nodes.len(), start.elapsed());
+
+	// What every frame would cost if the whole tree were laid out.
+	let total_lines = buffer.line_count(0);
+	let everything = LayoutMetrics {
+		visible_lines: total_lines,
+		..metrics
+	};
+	let start = Instant::now();
+	buffer.lay_out(&everything, &mut layout);
+	let full = start.elapsed();
+	println!("laying out all {} lines: {:?}", total_lines, full);
+
+	let start = Instant::now();
+	for frame in 0..FRAMES {
+		buffer.scroll = frame * (total_lines - metrics.visible_lines) / FRAMES;
+		buffer.lay_out(&metrics, &mut layout);
+	}
+	let frame = start.elapsed() / FRAMES as u32;
+	println!("scrolling through {} lines: {:?} per frame", total_lines, frame);
+	assert!(frame < FRAME_BUDGET, "a frame of scrolling took {:?}", frame);
+	assert!(frame * 100 < full, "a frame of scrolling took {:?}, laying out everything {:?}", frame, full);
+
+	// Typing into a string halfway down. Every keystroke changes the revision,
+	// so the next frame rebuilds the line layout before laying out the view.
+	let name = buffer.children(buffer.children(0)[ITEMS / 2])[1];
+	buffer.selections = vec![buffer.children(name)[0]];
+	buffer.scroll = buffer.line_of(name);
+	let start = Instant::now();
+	for _ in 0..KEYSTROKES {
+		buffer.input(JsonInput::Char('x'));
+		buffer.update_layout(&metrics, &mut layout);
+	}
+	println!("typing: {:?} per keystroke, including re-layout", start.elapsed() / KEYSTROKES as u32);
+}
@@ -0,0 +1,365 @@
+use crate::infer::Shape;
+use crate::{JsonBuffer, JsonNumber};
+
+const KEYWORDS: &[&str] = &[
+	"abstract", "as", "async", "await", "become", "box", "break", "const", "continue", "do", "dyn", "else", "enum",
+	"extern", "false", "final", "fn", "for", "if", "impl", "in", "let", "loop", "macro", "match", "mod", "move", "mut",
+	"override", "priv", "pub", "ref", "return", "static", "struct", "trait", "true", "try", "type", "typeof", "union",
+	"unsafe", "unsized", "use", "virtual", "where", "while", "yield",
+];
+// Keywords that can't be written as raw identifiers either.
+const RESERVED: &[&str] = &["crate", "self", "super", "Self", "_"];
+// Names the generated code uses for other types.
+const TAKEN: &[&str] = &["Deserialize", "Option", "Serialize", "String", "Value", "Vec"];
+
+impl JsonBuffer {
+	// Rust types that the document deserializes into with serde, named after
+	// the buffer's file. Each place objects appear becomes a struct, with array
+	// items sharing one; members that are missing or null somewhere become
+	// Options, and places holding more than one kind of value fall back to
+	// `serde_json::Value`.
+	pub fn rust_types(&self) -> String {
+		let root = self.path.as_ref()
+			.and_then(|path| path.file_stem())
+			.map(|stem| type_name(&stem.to_string_lossy()))
+			.unwrap_or_else(|| "Root".to_string());
+		let mut generator = Generator {
+			names: TAKEN.iter().map(|name| name.to_string()).collect(),
+			items: Vec::new(),
+		};
+		let shape = Shape::of(self, 0);
+		if shape.objects > 0 && shape.count() == shape.objects {
+			generator.push_struct(&shape, &root);
+		} else {
+			let index = generator.items.len();
+			generator.items.push(String::new());
+			let name = generator.unique(&root);
+			let alias = generator.type_of(&shape, &name);
+			generator.items[index] = format!("pub type {} = {};\n", name, alias);
+		}
+		let mut text = "use serde::{Deserialize, Serialize};\n".to_string();
+		for item in &generator.items {
+			text.push('\n');
+			text.push_str(item);
+		}
+		text
+	}
+}
+
+struct Generator {
+	names: Vec<String>,
+	// Type definitions, the root's first and the rest in the order their
+	// fields appear.
+	items: Vec<String>,
+}
+
+impl Generator {
+	fn unique(&mut self, name: &str) -> String {
+		let mut unique = name.to_string();
+		let mut n = 2;
+		while self.names.contains(&unique) {
+			unique = format!("{}{}", name, n);
+			n += 1;
+		}
+		self.names.push(unique.clone());
+		unique
+	}
+	fn push_struct(&mut self, shape: &Shape, name: &str) -> String {
+		let name = self.unique(name);
+		let index = self.items.len();
+		self.items.push(String::new());
+		let mut text = format!("#[derive(Debug, Serialize, Deserialize)]\npub struct {} {{", name);
+		if !shape.properties.is_empty() {
+			text.push('\n');
+		}
+		let mut fields: Vec<String> = Vec::new();
+		for (key, property) in &shape.properties {
+			let mut field = field_name(key);
+			let bare = field.trim_start_matches("r#").to_string();
+			let mut n = 2;
+			while fields.contains(&field) {
+				field = format!("{}_{}", bare, n);
+				n += 1;
+			}
+			fields.push(field.clone());
+			let mut ty = self.type_of(property, &type_name(key));
+			let mut attributes = Vec::new();
+			if field.trim_start_matches("r#") != key {
+				attributes.push(format!("rename = {:?}", key));
+			}
+			if property.count() < shape.objects {
+				if !ty.starts_with("Option<") {
+					ty = format!("Option<{}>", ty);
+				}
+				attributes.push("skip_serializing_if = \"Option::is_none\"".to_string());
+			}
+			if !attributes.is_empty() {
+				text.push_str(&format!("    #[serde({})]\n", attributes.join(", ")));
+			}
+			text.push_str(&format!("    pub {}: {},\n", field, ty));
+		}
+		text.push_str("}\n");
+		self.items[index] = text;
+		name
+	}
+	// The type for the values seen at `shape`, declaring a struct called
+	// `name` if they're objects.
+	fn type_of(&mut self, shape: &Shape, name: &str) -> String {
+		let numbers = shape.integers + shape.fractions;
+		let kinds = [shape.bools, numbers, shape.strings, shape.arrays, shape.objects].iter().filter(|&&count| count > 0).count();
+		if kinds != 1 {
+			return "serde_json::Value".to_string();
+		}
+		let ty = if shape.bools > 0 {
+			"bool".to_string()
+		} else if numbers > 0 {
+			number_type(shape).to_string()
+		} else if shape.strings > 0 {
+			"String".to_string()
+		} else if shape.arrays > 0 {
+			let item_name = singular(name);
+			let item_name = if item_name == name {format!("{}Item", name)} else {item_name};
+			match shape.items.as_ref().filter(|items| items.count() > 0) {
+				Some(items) => format!("Vec<{}>", self.type_of(items, &item_name)),
+				None => "Vec<serde_json::Value>".to_string(),
+			}
+		} else {
+			self.push_struct(shape, name)
+		};
+		if shape.nulls > 0 {
+			format!("Option<{}>", ty)
+		} else {
+			ty
+		}
+	}
+}
+
+// The narrowest of i64, u64 and f64 that holds every number seen.
+fn number_type(shape: &Shape) -> &'static str {
+	let bound = |n: &Option<JsonNumber>| n.as_ref().and_then(|n| n.as_integer().or_else(|| n.value.map(|value| value as i128)));
+	match (bound(&shape.minimum), bound(&shape.maximum)) {
+		_ if shape.fractions > 0 => "f64",
+		(Some(minimum), Some(maximum)) if minimum >= i64::MIN as i128 && maximum <= i64::MAX as i128 => "i64",
+		(Some(minimum), Some(maximum)) if minimum >= 0 && maximum <= u64::MAX as i128 => "u64",
+		_ => "f64",
+	}
+}
+
+// The words of `key`, split at punctuation and at lowercase-to-uppercase
+// changes, lowercased.
+fn words(key: &str) -> Vec<String> {
+	let mut words: Vec<String> = Vec::new();
+	let mut word = String::new();
+	let mut previous: Option<char> = None;
+	for c in key.chars() {
+		if !c.is_alphanumeric() {
+			if !word.is_empty() {
+				words.push(std::mem::take(&mut word));
+			}
+		} else {
+			if c.is_uppercase() && previous.is_some_and(|p| p.is_lowercase() || p.is_ascii_digit()) && !word.is_empty() {
+				words.push(std::mem::take(&mut word));
+			}
+			word.extend(c.to_lowercase());
+		}
+		previous = Some(c);
+	}
+	if !word.is_empty() {
+		words.push(word);
+	}
+	words
+}
+
+// `key` in snake_case, made into an identifier.
+fn field_name(key: &str) -> String {
+	let name = words(key).join("_");
+	if name.is_empty() {
+		"field".to_string()
+	} else if name.starts_with(|c: char| c.is_ascii_digit()) || RESERVED.contains(&name.as_str()) {
+		format!("_{}", name)
+	} else if KEYWORDS.contains(&name.as_str()) {
+		format!("r#{}", name)
+	} else {
+		name
+	}
+}
+
+// `key` in PascalCase, made into an identifier.
+fn type_name(key: &str) -> String {
+	let name: String = words(key).iter().map(|word| {
+		let mut chars = word.chars();
+		match chars.next() {
+			Some(first) => first.to_uppercase().chain(chars).collect(),
+			None => String::new(),
+		}
+	}).collect();
+	if name.is_empty() || name.starts_with(|c: char| c.is_ascii_digit()) || RESERVED.contains(&name.as_str()) {
+		format!("Type{}", name)
+	} else {
+		name
+	}
+}
+
+// The name for one item of an array called `name`.
+fn singular(name: &str) -> String {
+	if let Some(stem) = name.strip_suffix("ies").filter(|stem| !stem.is_empty()) {
+		format!("{}y", stem)
+	} else if name.ends_with('s') && !name.ends_with("ss") && name.len() > 1 {
+		name[..name.len() - 1].to_string()
+	} else {
+		name.to_string()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use std::path::PathBuf;
+	use std::process::Command;
+
+	const HEADER: &str = "use serde::{Deserialize, Serialize};\n\n";
+
+	fn types(text: &str) -> String {
+		JsonBuffer::parse(text).unwrap().rust_types()
+	}
+
+	#[test]
+	fn keys_that_arent_identifiers_are_renamed() {
+		assert_eq!(types(r#"{"2fa": true, "content-type": "json", "userId": 1}"#), HEADER.to_string() + "\
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Root {
+    #[serde(rename = \"2fa\")]
+    pub _2fa: bool,
+    #[serde(rename = \"content-type\")]
+    pub content_type: String,
+    #[serde(rename = \"userId\")]
+    pub user_id: i64,
+}
+");
+	}
+
+	#[test]
+	fn keywords_are_raw_or_prefixed() {
+		assert_eq!(types(r#"{"type": "a", "self": 1, "Self": 2, "crate": 3}"#), HEADER.to_string() + "\
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Root {
+    pub r#type: String,
+    #[serde(rename = \"self\")]
+    pub _self: i64,
+    #[serde(rename = \"Self\")]
+    pub _self_2: i64,
+    #[serde(rename = \"crate\")]
+    pub _crate: i64,
+}
+");
+	}
+
+	#[test]
+	fn items_share_one_struct_with_optional_members() {
+		let mut buffer = JsonBuffer::parse(r#"[
+			{"id": 1, "tags": ["a"], "owner": {"name": "x"}},
+			{"id": 2, "owner": {"name": "y", "age": 3}, "note": null}
+		]"#).unwrap();
+		buffer.path = Some(PathBuf::from("users.json"));
+		assert_eq!(buffer.rust_types(), HEADER.to_string() + "\
+pub type Users = Vec<User>;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct User {
+    pub id: i64,
+    #[serde(skip_serializing_if = \"Option::is_none\")]
+    pub tags: Option<Vec<String>>,
+    pub owner: Owner,
+    #[serde(skip_serializing_if = \"Option::is_none\")]
+    pub note: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Owner {
+    pub name: String,
+    #[serde(skip_serializing_if = \"Option::is_none\")]
+    pub age: Option<i64>,
+}
+");
+	}
+
+	#[test]
+	fn struct_names_are_unique() {
+		assert_eq!(types(r#"{"a": {"x": 1}, "b": {"a": {"y": 2}}, "root": {}, "value": {}}"#), HEADER.to_string() + "\
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Root {
+    pub a: A,
+    pub b: B,
+    pub root: Root2,
+    pub value: Value2,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct A {
+    pub x: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct B {
+    pub a: A2,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct A2 {
+    pub y: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Root2 {}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Value2 {}
+");
+	}
+
+	#[test]
+	fn numbers_get_the_narrowest_type_holding_them() {
+		let text = types(r#"{
+			"small": [-1, 9223372036854775807],
+			"unsigned": [0, 18446744073709551615],
+			"huge": 18446744073709551616,
+			"fraction": [1, 0.5],
+			"whole": 1e3
+		}"#);
+		assert!(text.contains("    pub small: Vec<i64>,\n"));
+		assert!(text.contains("    pub unsigned: Vec<u64>,\n"));
+		assert!(text.contains("    pub huge: f64,\n"));
+		assert!(text.contains("    pub fraction: Vec<f64>,\n"));
+		assert!(text.contains("    pub whole: i64,\n"));
+	}
+
+	// Compiles the generated types with rustc, minus serde's derives and
+	// attributes, to check every name is a valid identifier. Skipped if there's
+	// no rustc to run.
+	#[test]
+	fn generated_types_compile() {
+		let mut buffer = JsonBuffer::parse(r#"[
+			{"2fa": true, "type": "a", "self": null, "Self": [{"match": 1}], "value": {"Value": 2}},
+			{"2fa": false, "type": "b", "extra": 0.5}
+		]"#).unwrap();
+		buffer.path = Some(PathBuf::from("the-items.json"));
+		let code: String = buffer.rust_types().lines()
+			.filter(|line| !line.trim_start().starts_with("#[") && !line.starts_with("use "))
+			.map(|line| format!("{}\n", line))
+			.collect();
+		let dir = std::env::temp_dir().join(format!("codegen-{}", std::process::id()));
+		std::fs::create_dir_all(&dir).unwrap();
+		let source = dir.join("types.rs");
+		std::fs::write(&source, format!("mod serde_json {{\n    pub struct Value;\n}}\n\n{}", code)).unwrap();
+		let output = Command::new(std::env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string()))
+			.args(["--crate-type", "lib", "--edition", "2018", "--emit", "metadata", "--out-dir"])
+			.arg(&dir)
+			.arg(&source)
+			.output();
+		std::fs::remove_dir_all(&dir).ok();
+		if let Ok(output) = output {
+			assert!(output.status.success(), "{}\n{}", code, String::from_utf8_lossy(&output.stderr));
+		}
+	}
+}
@@ -0,0 +1,338 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use skulpin::app::{InputState, VirtualKeyCode};
+
+use super::keymap::{Command, Modifiers};
+
+// Names offered by tab completion, in the order they're cycled through.
+const EX_COMMANDS: &[&str] = &["apply", "diff", "diffoff", "edit", "goto", "inferschema", "merge", "quit", "schema", "select", "sort", "write", "writepatch", "writerust", "wq"];
+
+// Printable keys with their unshifted and shifted characters, for a US layout.
+const CHAR_KEYS: &[(VirtualKeyCode, char, char)] = &[
+	(VirtualKeyCode::A, 'a', 'A'), (VirtualKeyCode::B, 'b', 'B'), (VirtualKeyCode::C, 'c', 'C'),
+	(VirtualKeyCode::D, 'd', 'D'), (VirtualKeyCode::E, 'e', 'E'), (VirtualKeyCode::F, 'f', 'F'),
+	(VirtualKeyCode::G, 'g', 'G'), (VirtualKeyCode::H, 'h', 'H'), (VirtualKeyCode::I, 'i', 'I'),
+	(VirtualKeyCode::J, 'j', 'J'), (VirtualKeyCode::K, 'k', 'K'), (VirtualKeyCode::L, 'l', 'L'),
+	(VirtualKeyCode::M, 'm', 'M'), (VirtualKeyCode::N, 'n', 'N'), (VirtualKeyCode::O, 'o', 'O'),
+	(VirtualKeyCode::P, 'p', 'P'), (VirtualKeyCode::Q, 'q', 'Q'), (VirtualKeyCode::R, 'r', 'R'),
+	(VirtualKeyCode::S, 's', 'S'), (VirtualKeyCode::T, 't', 'T'), (VirtualKeyCode::U, 'u', 'U'),
+	(VirtualKeyCode::V, 'v', 'V'), (VirtualKeyCode::W, 'w', 'W'), (VirtualKeyCode::X, 'x', 'X'),
+	(VirtualKeyCode::Y, 'y', 'Y'), (VirtualKeyCode::Z, 'z', 'Z'),
+	(VirtualKeyCode::Key1, '1', '!'), (VirtualKeyCode::Key2, '2', '@'), (VirtualKeyCode::Key3, '3', '#'),
+	(VirtualKeyCode::Key4, '4', '$'), (VirtualKeyCode::Key5, '5', '%'), (VirtualKeyCode::Key6, '6', '^'),
+	(VirtualKeyCode::Key7, '7', '&'), (VirtualKeyCode::Key8, '8', '*'), (VirtualKeyCode::Key9, '9', '('),
+	(VirtualKeyCode::Key0, '0', ')'),
+	(VirtualKeyCode::Space, ' ', ' '), (VirtualKeyCode::Minus, '-', '_'), (VirtualKeyCode::Equals, '=', '+'),
+	(VirtualKeyCode::LBracket, '[', '{'), (VirtualKeyCode::RBracket, ']', '}'), (VirtualKeyCode::Backslash, '\\', '|'),
+	(VirtualKeyCode::Semicolon, ';', ':'), (VirtualKeyCode::Apostrophe, '\'', '"'), (VirtualKeyCode::Grave, '`', '~'),
+	(VirtualKeyCode::Comma, ',', '<'), (VirtualKeyCode::Period, '.', '>'), (VirtualKeyCode::Slash, '/', '?'),
+];
+
+pub enum LineEvent {
+	Submit(String),
+	Cancel,
+}
+
+// A command typed after ":".
+#[derive(Debug, PartialEq)]
+pub enum ExCommand {
+	Write(Option<PathBuf>),
+	Quit { force: bool },
+	WriteQuit,
+	Edit { path: PathBuf, force: bool },
+	// Switches to the buffer that was open before, as ":e#".
+	Alternate,
+	// Applies a JSON Patch, or a merge patch if the file holds an object.
+	Apply(PathBuf),
+	// Compares the buffer with a file, or with its own file on disk, matching
+	// array items by `id_field` if given.
+	Diff { path: Option<PathBuf>, id_field: Option<String> },
+	DiffOff,
+	// Validates against the schema in a file, or stops validating.
+	Schema(Option<PathBuf>),
+	// Merges the changes `theirs` made to `base` into the buffer.
+	Merge { base: PathBuf, theirs: PathBuf },
+	// Opens a schema describing the buffer in a new buffer.
+	InferSchema { force: bool },
+	// Saves the session's edits as a JSON Patch.
+	WritePatch(PathBuf),
+	// Saves Rust types the document deserializes into.
+	WriteRust(PathBuf),
+	Select(String),
+	// Selects the node at a JSON Pointer.
+	Goto(String),
+	Sort,
+	// Any command that can be bound to a key, by its keymap name.
+	Run(Command),
+}
+
+impl ExCommand {
+	// A name, optionally followed by "!" to discard unsaved changes, then an
+	// argument running to the end of the line.
+	pub fn parse(text: &str) -> Result<ExCommand, String> {
+		let text = text.trim();
+		let (name, argument) = match text.find(char::is_whitespace) {
+			Some(split) => (&text[..split], text[split..].trim()),
+			None => (text, ""),
+		};
+		let (name, force) = match name.strip_suffix('!') {
+			Some(name) => (name, true),
+			None => (name, false),
+		};
+		let path = || if argument.is_empty() {None} else {Some(PathBuf::from(argument))};
+		let command = match name {
+			"w" | "write" => ExCommand::Write(path()),
+			"q" | "quit" => ExCommand::Quit { force },
+			"wq" | "x" => ExCommand::WriteQuit,
+			"e#" => ExCommand::Alternate,
+			"e" | "edit" if argument == "#" => ExCommand::Alternate,
+			"e" | "edit" => ExCommand::Edit {
+				path: path().ok_or_else(|| format!("{} needs a file name", name))?,
+				force,
+			},
+			"apply" => ExCommand::Apply(path().ok_or("apply needs a file name")?),
+			"diff" => {
+				let mut words = argument.split_whitespace();
+				let path = words.next().map(PathBuf::from);
+				let id_field = words.next().map(str::to_string);
+				if words.next().is_some() {
+					return Err("diff takes a file name and an id field".to_string());
+				}
+				ExCommand::Diff { path, id_field }
+			},
+			"diffoff" => ExCommand::DiffOff,
+			"schema" if argument.is_empty() => ExCommand::Schema(None),
+			"schema" => ExCommand::Schema(Some(PathBuf::from(argument))),
+			"merge" => {
+				let mut words = argument.split_whitespace();
+				match (words.next(), words.next(), words.next()) {
+					(Some(base), Some(theirs), None) => ExCommand::Merge {
+						base: PathBuf::from(base),
+						theirs: PathBuf::from(theirs),
+					},
+					_ => return Err("merge takes a base file and their file".to_string()),
+				}
+			},
+			"inferschema" => ExCommand::InferSchema { force },
+			"writepatch" => ExCommand::WritePatch(path().ok_or("writepatch needs a file name")?),
+			"writerust" => ExCommand::WriteRust(path().ok_or("writerust needs a file name")?),
+			"select" if argument.is_empty() => return Err("select needs a query".to_string()),
+			"select" => ExCommand::Select(argument.to_string()),
+			"goto" => ExCommand::Goto(argument.to_string()),
+			"sort" => ExCommand::Sort,
+			_ => return Command::parse(text).map(ExCommand::Run).map_err(|_| format!("not a command: {}", name)),
+		};
+		match command {
+			ExCommand::Write(_) | ExCommand::Edit { .. } | ExCommand::Alternate | ExCommand::Apply(_) | ExCommand::Diff { .. } | ExCommand::Merge { .. } | ExCommand::Schema(_) | ExCommand::WritePatch(_) | ExCommand::WriteRust(_) | ExCommand::Select(_) | ExCommand::Goto(_) => {},
+			_ if !argument.is_empty() => return Err(format!("{} doesn't take an argument", name)),
+			_ => {},
+		}
+		Ok(command)
+	}
+}
+
+// The line editor shown in place of the status line in command mode.
+pub struct CommandLine {
+	pub text: String,
+	// Cursor position in chars.
+	pub cursor: usize,
+	history: Vec<String>,
+	// The history entry being shown, or history.len() for the line being typed.
+	history_index: usize,
+	// The line being typed, kept while browsing history.
+	draft: String,
+	// Lines tab completion cycles through, until the text is next edited.
+	completions: Vec<String>,
+	completion_index: usize,
+}
+
+impl CommandLine {
+	pub fn new() -> CommandLine {
+		CommandLine {
+			text: String::new(),
+			cursor: 0,
+			history: Vec::new(),
+			history_index: 0,
+			draft: String::new(),
+			completions: Vec::new(),
+			completion_index: 0,
+		}
+	}
+	pub fn open(&mut self) {
+		self.set_text(String::new());
+		self.history_index = self.history.len();
+	}
+	pub fn update(&mut self, input_state: &InputState) -> Option<LineEvent> {
+		let modifiers = Modifiers::from_state(input_state);
+		if input_state.is_key_just_down(VirtualKeyCode::Escape) {
+			return Some(LineEvent::Cancel);
+		} else if input_state.is_key_just_down(VirtualKeyCode::Return) {
+			return Some(self.submit());
+		} else if input_state.is_key_just_down(VirtualKeyCode::Tab) {
+			self.complete(!modifiers.shift);
+		} else if input_state.is_key_just_down(VirtualKeyCode::Up) {
+			self.browse_history(-1);
+		} else if input_state.is_key_just_down(VirtualKeyCode::Down) {
+			self.browse_history(1);
+		} else if input_state.is_key_just_down(VirtualKeyCode::Left) {
+			self.cursor = self.cursor.saturating_sub(1);
+		} else if input_state.is_key_just_down(VirtualKeyCode::Right) {
+			self.cursor = (self.cursor + 1).min(self.text.chars().count());
+		} else if input_state.is_key_just_down(VirtualKeyCode::Home) {
+			self.cursor = 0;
+		} else if input_state.is_key_just_down(VirtualKeyCode::End) {
+			self.cursor = self.text.chars().count();
+		} else if input_state.is_key_just_down(VirtualKeyCode::Back) {
+			// Backspacing over an empty line leaves command mode, as in vim.
+			if self.text.is_empty() {
+				return Some(LineEvent::Cancel);
+			}
+			if self.cursor > 0 {
+				self.cursor -= 1;
+				self.remove_at_cursor();
+			}
+		} else if input_state.is_key_just_down(VirtualKeyCode::Delete) {
+			if self.cursor < self.text.chars().count() {
+				self.remove_at_cursor();
+			}
+		} else if !modifiers.ctrl && !modifiers.alt && !modifiers.logo {
+			let typed = CHAR_KEYS.iter()
+				.find(|(key, _, _)| input_state.is_key_just_down(*key))
+				.map(|&(_, plain, shifted)| if modifiers.shift {shifted} else {plain});
+			if let Some(c) = typed {
+				let offset = self.byte_offset();
+				self.text.insert(offset, c);
+				self.cursor += 1;
+				self.completions.clear();
+			}
+		}
+		None
+	}
+	fn submit(&mut self) -> LineEvent {
+		let text = std::mem::take(&mut self.text);
+		if !text.trim().is_empty() && self.history.last() != Some(&text) {
+			self.history.push(text.clone());
+		}
+		self.open();
+		LineEvent::Submit(text)
+	}
+	fn set_text(&mut self, text: String) {
+		self.cursor = text.chars().count();
+		self.text = text;
+		self.completions.clear();
+	}
+	fn byte_offset(&self) -> usize {
+		self.text.char_indices().nth(self.cursor).map(|(i, _)| i).unwrap_or_else(|| self.text.len())
+	}
+	fn remove_at_cursor(&mut self) {
+		let offset = self.byte_offset();
+		self.text.remove(offset);
+		self.completions.clear();
+	}
+	fn browse_history(&mut self, step: isize) {
+		let index = self.history_index as isize + step;
+		if index < 0 || index > self.history.len() as isize {
+			return;
+		}
+		if self.history_index == self.history.len() {
+			self.draft = self.text.clone();
+		}
+		self.history_index = index as usize;
+		let text = self.history.get(self.history_index).cloned().unwrap_or_else(|| self.draft.clone());
+		self.set_text(text);
+	}
+	// Replaces the line with the next (or previous) completion of what was
+	// typed, wrapping back around to the typed text.
+	fn complete(&mut self, forward: bool) {
+		if self.completions.is_empty() {
+			let mut completions = completions_for(&self.text);
+			if completions.is_empty() {
+				return;
+			}
+			completions.insert(0, self.text.clone());
+			self.completions = completions;
+			self.completion_index = 0;
+		}
+		let len = self.completions.len();
+		self.completion_index = if forward {
+			(self.completion_index + 1) % len
+		} else {
+			(self.completion_index + len - 1) % len
+		};
+		self.text = self.completions[self.completion_index].clone();
+		self.cursor = self.text.chars().count();
+	}
+}
+
+// Whole lines that `text` could be completed to: command names for the first
+// word, and paths for the argument of commands that take a file.
+fn completions_for(text: &str) -> Vec<String> {
+	match text.find(' ') {
+		None => EX_COMMANDS.iter()
+			.filter(|name| name.starts_with(text) && **name != text)
+			.map(|name| name.to_string())
+			.collect(),
+		Some(split) => {
+			let (name, argument) = (&text[..split], text[split..].trim_start());
+			let name = name.trim_end_matches('!');
+			if !["apply", "diff", "e", "edit", "merge", "schema", "w", "write", "writepatch", "writerust"].contains(&name) {
+				return Vec::new();
+			}
+			// Merge takes two files, so its second word is completed too.
+			let argument = if name == "merge" {
+				argument.rsplit(' ').next().unwrap_or(argument)
+			} else {
+				argument
+			};
+			let prefix = &text[..text.len() - argument.len()];
+			complete_path(argument)
+				.into_iter()
+				.map(|path| format!("{}{}", prefix, path))
+				.collect()
+		},
+	}
+}
+
+// Entries of the directory `partial` points into whose names start with its
+// last component. Directories end in "/" so completion can carry on into them.
+fn complete_path(partial: &str) -> Vec<String> {
+	let (dir, name) = match partial.rfind('/') {
+		Some(split) => (&partial[..=split], &partial[split + 1..]),
+		None => ("", partial),
+	};
+	let entries = match fs::read_dir(if dir.is_empty() {Path::new(".")} else {Path::new(dir)}) {
+		Ok(entries) => entries,
+		Err(_) => return Vec::new(),
+	};
+	let mut paths: Vec<String> = entries
+		.filter_map(|entry| entry.ok())
+		.filter_map(|entry| {
+			let file_name = entry.file_name().into_string().ok()?;
+			if !file_name.starts_with(name) || (name.is_empty() && file_name.starts_with('.')) {
+				return None;
+			}
+			let is_dir = entry.file_type().map(|kind| kind.is_dir()).unwrap_or(false);
+			Some(format!("{}{}{}", dir, file_name, if is_dir {"/"} else {""}))
+		})
+		.collect();
+	paths.sort();
+	paths
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn parses_buffer_commands() {
+		assert_eq!(ExCommand::parse("e#"), Ok(ExCommand::Alternate));
+		assert_eq!(ExCommand::parse("edit #"), Ok(ExCommand::Alternate));
+		assert_eq!(ExCommand::parse("e! a.json"), Ok(ExCommand::Edit { path: PathBuf::from("a.json"), force: true }));
+		assert!(ExCommand::parse("e").is_err());
+		assert_eq!(ExCommand::parse("inferschema"), Ok(ExCommand::InferSchema { force: false }));
+		assert_eq!(ExCommand::parse("inferschema!"), Ok(ExCommand::InferSchema { force: true }));
+		assert_eq!(ExCommand::parse("q!"), Ok(ExCommand::Quit { force: true }));
+		assert!(ExCommand::parse("q now").is_err());
+	}
+}
@@ -0,0 +1,260 @@
+use serde_json::{Map, Value};
+
+use crate::schema::{Schema, Step};
+use crate::{JsonBuffer, JsonVariant};
+
+// How deep a skeleton fills in required properties, should a schema refer
+// to itself.
+const MAX_SKELETON_DEPTH: usize = 8;
+
+// What choosing a completion does to the node it was offered for.
+#[derive(Clone, Debug, PartialEq)]
+pub enum CompletionAction {
+	// Renames the entry, and fills in its value if it's still null.
+	Key { key: String, value: Option<Value> },
+	// Replaces the node.
+	Value(Value),
+	// Adds members to the object.
+	Members(Vec<(String, Value)>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Completion {
+	pub label: String,
+	pub action: CompletionAction,
+}
+
+impl JsonBuffer {
+	// What the schema suggests for `node`: allowed property names for an
+	// entry, matching what's been typed of the key; allowed values for a
+	// string or null; and the missing required properties of an object.
+	pub fn completions(&self, node: usize) -> Vec<Completion> {
+		let schema = match self.schema() {
+			Some(schema) => schema,
+			None => return Vec::new(),
+		};
+		let locations = self.schema_locations(&schema, node);
+		match &self.nodes[node].variant {
+			JsonVariant::ObjectEntry(typed, value) => {
+				let object = self.nodes[node].parent;
+				let object_locations = self.schema_locations(&schema, object);
+				let fill = matches!(self.nodes[*value].variant, JsonVariant::Null);
+				property_names(&schema, &object_locations)
+					.into_iter()
+					.filter(|key| key.starts_with(typed.as_str()) && key != typed && !self.has_member(object, key))
+					.map(|key| {
+						let value = if fill {
+							Some(skeleton(&schema, &object_locations, &key))
+						} else {
+							None
+						};
+						Completion {
+							label: key.clone(),
+							action: CompletionAction::Key { key, value },
+						}
+					})
+					.collect()
+			},
+			JsonVariant::String(typed) => {
+				allowed_values(&schema, &locations)
+					.into_iter()
+					.filter(|value| matches!(value, Value::String(s) if s.starts_with(typed.as_str()) && s != typed))
+					.map(value_completion)
+					.collect()
+			},
+			JsonVariant::Null => {
+				let mut values = allowed_values(&schema, &locations);
+				let skeleton = skeleton_at(&schema, &locations, 0);
+				if !skeleton.is_null() && !values.contains(&skeleton) {
+					values.push(skeleton);
+				}
+				values.into_iter().filter(|value| !value.is_null()).map(value_completion).collect()
+			},
+			JsonVariant::Object(_) => {
+				let missing: Vec<(String, Value)> = required_names(&schema, &locations)
+					.into_iter()
+					.filter(|key| !self.has_member(node, key))
+					.map(|key| {
+						let value = skeleton(&schema, &locations, &key);
+						(key, value)
+					})
+					.collect();
+				let mut completions = Vec::new();
+				if missing.len() > 1 {
+					completions.push(Completion {
+						label: "all required properties".to_string(),
+						action: CompletionAction::Members(missing.clone()),
+					});
+				}
+				for (key, value) in missing {
+					completions.push(Completion {
+						label: format!("\"{}\": {}", key, value),
+						action: CompletionAction::Members(vec![(key, value)]),
+					});
+				}
+				completions
+			},
+			_ => Vec::new(),
+		}
+	}
+	// Carries out a completion offered for `node`, leaving it or whatever
+	// replaced it selected.
+	pub fn complete(&mut self, node: usize, completion: &Completion) {
+		self.edit();
+		match &completion.action {
+			CompletionAction::Key { key, value } => {
+				let from = self.pointer_of(node);
+				let child = match &mut self.nodes[node].variant {
+					JsonVariant::ObjectEntry(old_key, child) => {
+						*old_key = key.clone();
+						*child
+					},
+					_ => return,
+				};
+				self.record_rename(node, from);
+				if let Some(value) = value {
+					let new = self.push_value(value);
+					self.replace_node(child, new);
+					self.record_replace(new);
+				}
+				self.selections = vec![node];
+			},
+			CompletionAction::Value(value) => {
+				let new = self.push_value(value);
+				self.replace_node(node, new);
+				let new = if node == 0 {0} else {new};
+				self.record_replace(new);
+				self.selections = vec![new];
+			},
+			CompletionAction::Members(members) => {
+				let mut entries = Vec::new();
+				for (key, value) in members {
+					let child = self.push_value(value);
+					let entry = self.push_node(JsonVariant::ObjectEntry(key.clone(), child));
+					self.link_children(entry);
+					entries.push(entry);
+				}
+				if let Some(children) = self.children_mut(node) {
+					children.extend(entries.iter().copied());
+				}
+				self.link_children(node);
+				self.record_added(entries);
+				self.selections = vec![node];
+			},
+		}
+		self.primary = 0;
+	}
+	fn has_member(&self, object: usize, key: &str) -> bool {
+		self.children(object).iter().any(|&entry| {
+			matches!(&self.nodes[entry].variant, JsonVariant::ObjectEntry(k, _) if k==key)
+		})
+	}
+}
+
+fn value_completion(value: Value) -> Completion {
+	Completion {
+		label: value.to_string(),
+		action: CompletionAction::Value(value),
+	}
+}
+
+fn keywords<'a>(schema: &'a Schema, location: &str) -> Option<&'a Map<String, Value>> {
+	schema.at(location).and_then(Value::as_object)
+}
+
+// Names from `properties` and `required`, in the order the schema gives them.
+fn property_names(schema: &Schema, locations: &[String]) -> Vec<String> {
+	let mut names: Vec<String> = Vec::new();
+	for keywords in locations.iter().filter_map(|location| keywords(schema, location)) {
+		let properties = keywords.get("properties").and_then(Value::as_object).into_iter().flat_map(|properties| properties.keys());
+		for name in properties.chain(required_in(keywords)) {
+			if !names.contains(name) {
+				names.push(name.clone());
+			}
+		}
+	}
+	names
+}
+
+fn required_names(schema: &Schema, locations: &[String]) -> Vec<String> {
+	let mut names: Vec<String> = Vec::new();
+	for keywords in locations.iter().filter_map(|location| keywords(schema, location)) {
+		for name in required_in(keywords) {
+			if !names.contains(name) {
+				names.push(name.clone());
+			}
+		}
+	}
+	names
+}
+
+fn required_in(keywords: &Map<String, Value>) -> impl Iterator<Item = &String> {
+	keywords.get("required").and_then(Value::as_array).into_iter().flatten().filter_map(|name| match name {
+		Value::String(name) => Some(name),
+		_ => None,
+	})
+}
+
+// Values named by `enum`, `const` and `default`.
+fn allowed_values(schema: &Schema, locations: &[String]) -> Vec<Value> {
+	let mut values: Vec<Value> = Vec::new();
+	for keywords in locations.iter().filter_map(|location| keywords(schema, location)) {
+		let listed = keywords.get("enum").and_then(Value::as_array).into_iter().flatten();
+		for value in listed.chain(keywords.get("const")).chain(keywords.get("default")) {
+			if !values.contains(value) {
+				values.push(value.clone());
+			}
+		}
+	}
+	values
+}
+
+// A starting value for the property `key` of an object at `locations`.
+fn skeleton(schema: &Schema, locations: &[String], key: &str) -> Value {
+	let step = Step::Key(key);
+	let children: Vec<String> = locations.iter().flat_map(|location| schema.child(location, &step)).collect();
+	let children: Vec<String> = children.iter().flat_map(|location| schema.expand(location)).collect();
+	skeleton_at(schema, &children, 0)
+}
+
+// The schema's default if it has one, or else the simplest value of its
+// type, with objects given their required properties.
+fn skeleton_at(schema: &Schema, locations: &[String], depth: usize) -> Value {
+	let all: Vec<&Map<String, Value>> = locations.iter().filter_map(|location| keywords(schema, location)).collect();
+	for keyword in &["default", "const"] {
+		if let Some(value) = all.iter().find_map(|keywords| keywords.get(*keyword)) {
+			return value.clone();
+		}
+	}
+	if let Some(value) = all.iter().find_map(|keywords| keywords.get("enum").and_then(Value::as_array).and_then(|values| values.first())) {
+		return value.clone();
+	}
+	let kind = all.iter().find_map(|keywords| match keywords.get("type") {
+		Some(Value::String(kind)) => Some(kind.as_str()),
+		Some(Value::Array(kinds)) => kinds.iter().filter_map(Value::as_str).find(|&kind| kind != "null"),
+		_ if keywords.contains_key("properties") || keywords.contains_key("required") => Some("object"),
+		_ => None,
+	});
+	match kind {
+		Some("object") => {
+			let mut members = Map::new();
+			if depth < MAX_SKELETON_DEPTH {
+				for key in required_names(schema, locations) {
+					let step = Step::Key(&key);
+					let children: Vec<String> = locations.iter()
+						.flat_map(|location| schema.child(location, &step))
+						.flat_map(|location| schema.expand(&location))
+						.collect();
+					let value = skeleton_at(schema, &children, depth + 1);
+					members.insert(key, value);
+				}
+			}
+			Value::Object(members)
+		},
+		Some("array") => Value::Array(Vec::new()),
+		Some("string") => Value::String(String::new()),
+		Some("integer") | Some("number") => Value::from(0),
+		Some("boolean") => Value::Bool(false),
+		_ => Value::Null,
+	}
+}
@@ -0,0 +1,279 @@
+use std::collections::{HashMap, VecDeque};
+
+use crate::{JsonBuffer, JsonVariant};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ChangeKind {
+	Added,
+	Removed,
+	Changed,
+	Moved,
+}
+
+// One difference between two documents. Object members are reported by their
+// entry and array items by the item itself. Changes are reported on the
+// values that differ, and moves on array items whose order changed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Difference {
+	pub kind: ChangeKind,
+	// The node in the old document, unless it was added.
+	pub old: Option<usize>,
+	// The node in the new document, unless it was removed.
+	pub new: Option<usize>,
+	pub old_path: Option<String>,
+	pub new_path: Option<String>,
+}
+
+impl JsonBuffer {
+	// The differences from this document to `new`. Object members are matched
+	// by key, so their order doesn't matter. Array items are matched by the
+	// value of `id_field` when they're objects that have it, then by being
+	// equal, and then those left over are compared in order.
+	pub fn diff(&self, new: &JsonBuffer, id_field: Option<&str>) -> Vec<Difference> {
+		let mut differ = Differ {
+			old: self,
+			new,
+			id_field,
+			differences: Vec::new(),
+		};
+		differ.compare(0, 0);
+		differ.differences
+	}
+}
+
+struct Differ<'a> {
+	old: &'a JsonBuffer,
+	new: &'a JsonBuffer,
+	id_field: Option<&'a str>,
+	differences: Vec<Difference>,
+}
+
+impl<'a> Differ<'a> {
+	fn push(&mut self, kind: ChangeKind, old: Option<usize>, new: Option<usize>) {
+		self.differences.push(Difference {
+			kind,
+			old,
+			new,
+			old_path: old.map(|node| self.old.pointer_of(node)),
+			new_path: new.map(|node| self.new.pointer_of(node)),
+		});
+	}
+	fn compare(&mut self, old: usize, new: usize) {
+		match (&self.old.nodes[old].variant, &self.new.nodes[new].variant) {
+			(JsonVariant::Object(old_entries), JsonVariant::Object(new_entries)) => {
+				let old_keys = entries_by_key(self.old, old_entries);
+				let new_keys = entries_by_key(self.new, new_entries);
+				for &entry in old_entries {
+					match new_keys.get(entry_key(self.old, entry)) {
+						Some(&new_entry) => self.compare(self.old.children(entry)[0], self.new.children(new_entry)[0]),
+						None => self.push(ChangeKind::Removed, Some(entry), None),
+					}
+				}
+				for &entry in new_entries {
+					if !old_keys.contains_key(entry_key(self.new, entry)) {
+						self.push(ChangeKind::Added, None, Some(entry));
+					}
+				}
+			},
+			(JsonVariant::Array(old_items), JsonVariant::Array(new_items)) => {
+				self.compare_arrays(old_items, new_items);
+			},
+			(JsonVariant::Null, JsonVariant::Null) => {},
+			(JsonVariant::Bool(a), JsonVariant::Bool(b)) if a == b => {},
+			(JsonVariant::Number(a), JsonVariant::Number(b)) if a == b => {},
+			(JsonVariant::String(a), JsonVariant::String(b)) if a == b => {},
+			_ => self.push(ChangeKind::Changed, Some(old), Some(new)),
+		}
+	}
+	fn compare_arrays(&mut self, old_items: &[usize], new_items: &[usize]) {
+		let mut old_used = vec![false; old_items.len()];
+		let mut new_used = vec![false; new_items.len()];
+		// Pairs of positions in the old and new arrays, and whether the pair
+		// still needs comparing, which those matched as equal don't.
+		let mut pairs: Vec<(usize, usize, bool)> = Vec::new();
+
+		if let Some(field) = self.id_field {
+			let mut new_ids: HashMap<String, usize> = HashMap::new();
+			for (j, &item) in new_items.iter().enumerate() {
+				if let Some(id) = item_id(self.new, item, field) {
+					new_ids.entry(id).or_insert(j);
+				}
+			}
+			for (i, &item) in old_items.iter().enumerate() {
+				if let Some(j) = item_id(self.old, item, field).and_then(|id| new_ids.remove(&id)) {
+					old_used[i] = true;
+					new_used[j] = true;
+					pairs.push((i, j, true));
+				}
+			}
+		}
+
+		let mut new_values: HashMap<String, VecDeque<usize>> = HashMap::new();
+		for (j, &item) in new_items.iter().enumerate().filter(|(j, _)| !new_used[*j]) {
+			new_values.entry(canonical(self.new, item)).or_default().push_back(j);
+		}
+		for (i, &item) in old_items.iter().enumerate() {
+			if old_used[i] {
+				continue;
+			}
+			if let Some(j) = new_values.get_mut(&canonical(self.old, item)).and_then(VecDeque::pop_front) {
+				old_used[i] = true;
+				new_used[j] = true;
+				pairs.push((i, j, false));
+			}
+		}
+
+		// Items outside the longest run that kept its relative order are the
+		// ones that moved.
+		pairs.sort_unstable();
+		let in_order = longest_increasing(&pairs.iter().map(|&(_, j, _)| j).collect::<Vec<_>>());
+		for (&(i, j, compare), kept) in pairs.iter().zip(in_order) {
+			if !kept {
+				self.push(ChangeKind::Moved, Some(old_items[i]), Some(new_items[j]));
+			}
+			if compare {
+				self.compare(old_items[i], new_items[j]);
+			}
+		}
+
+		let old_left = old_items.iter().zip(&old_used).filter(|(_, &used)| !used).map(|(&item, _)| item);
+		let mut new_left = new_items.iter().zip(&new_used).filter(|(_, &used)| !used).map(|(&item, _)| item);
+		for old in old_left {
+			match new_left.next() {
+				Some(new) => self.compare(old, new),
+				None => self.push(ChangeKind::Removed, Some(old), None),
+			}
+		}
+		for new in new_left {
+			self.push(ChangeKind::Added, None, Some(new));
+		}
+	}
+}
+
+fn entry_key(buffer: &JsonBuffer, entry: usize) -> &str {
+	match &buffer.nodes[entry].variant {
+		JsonVariant::ObjectEntry(key, _) => key,
+		_ => "",
+	}
+}
+
+// The first entry for each key, should a key appear twice.
+fn entries_by_key<'b>(buffer: &'b JsonBuffer, entries: &[usize]) -> HashMap<&'b str, usize> {
+	let mut keys = HashMap::new();
+	for &entry in entries {
+		keys.entry(entry_key(buffer, entry)).or_insert(entry);
+	}
+	keys
+}
+
+fn item_id(buffer: &JsonBuffer, item: usize, field: &str) -> Option<String> {
+	buffer.children(item).iter()
+		.find(|&&entry| matches!(&buffer.nodes[entry].variant, JsonVariant::ObjectEntry(key, _) if key==field))
+		.map(|&entry| canonical(buffer, buffer.children(entry)[0]))
+}
+
+// Serialized with object members in sorted order, so equal values always
+// give the same text.
+fn canonical(buffer: &JsonBuffer, node: usize) -> String {
+	buffer.value_at(node).to_string()
+}
+
+// Marks the members of one longest strictly increasing subsequence.
+fn longest_increasing(values: &[usize]) -> Vec<bool> {
+	// tails[k] is the position of the smallest value ending a run of length k+1.
+	let mut tails: Vec<usize> = Vec::new();
+	let mut previous = vec![None; values.len()];
+	for (i, &value) in values.iter().enumerate() {
+		let k = tails.partition_point(|&t| values[t] < value);
+		previous[i] = if k > 0 {Some(tails[k - 1])} else {None};
+		if k == tails.len() {
+			tails.push(i);
+		} else {
+			tails[k] = i;
+		}
+	}
+	let mut members = vec![false; values.len()];
+	let mut cur = tails.last().copied();
+	while let Some(i) = cur {
+		members[i] = true;
+		cur = previous[i];
+	}
+	members
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use ChangeKind::*;
+
+	// Each difference as its kind and the paths on either side.
+	fn differences(old: &str, new: &str, id_field: Option<&str>) -> Vec<(ChangeKind, Option<String>, Option<String>)> {
+		let old = JsonBuffer::parse(old).unwrap();
+		let new = JsonBuffer::parse(new).unwrap();
+		old.diff(&new, id_field).into_iter().map(|d| (d.kind, d.old_path, d.new_path)).collect()
+	}
+
+	fn path(path: &str) -> Option<String> {
+		Some(path.to_string())
+	}
+
+	#[test]
+	fn key_order_doesnt_matter() {
+		assert_eq!(differences(r#"{"a": 1, "b": {"c": 2, "d": 3}}"#, r#"{"b": {"d": 3, "c": 2}, "a": 1}"#, None), vec![]);
+		assert_eq!(differences(r#"{"a": 1, "b": 2}"#, r#"{"c": 3, "b": 4}"#, None), vec![
+			(Removed, path("/a"), None),
+			(Changed, path("/b"), path("/b")),
+			(Added, None, path("/c")),
+		]);
+		// Equal items are matched however their members are ordered.
+		assert_eq!(differences(r#"[{"x": 1, "y": 2}]"#, r#"[{"y": 2, "x": 1}]"#, None), vec![]);
+	}
+
+	#[test]
+	fn items_are_matched_by_id() {
+		let old = r#"[{"id": 1, "v": "a"}, {"id": 2, "v": "b"}]"#;
+		let new = r#"[{"id": 2, "v": "c"}, {"id": 1, "v": "a"}]"#;
+		assert_eq!(differences(old, new, Some("id")), vec![
+			(Moved, path("/0"), path("/1")),
+			(Changed, path("/1/v"), path("/0/v")),
+		]);
+		// Without the id, the changed item is only compared with what's left
+		// over, so nothing counts as having moved.
+		assert_eq!(differences(old, new, None), vec![
+			(Changed, path("/1/v"), path("/0/v")),
+		]);
+		let new = r#"[{"id": 3, "v": "b"}, {"id": 1, "v": "a"}]"#;
+		assert_eq!(differences(old, new, Some("id")), vec![
+			(Changed, path("/1/id"), path("/0/id")),
+		]);
+		// Items missing the field, or that aren't objects, fall back to value.
+		assert_eq!(differences(r#"[{"v": 1}, 2, {"id": 1}]"#, r#"[{"id": 1}, {"v": 1}, 2]"#, Some("id")), vec![
+			(Moved, path("/2"), path("/0")),
+		]);
+	}
+
+	#[test]
+	fn moves_are_outside_the_longest_increasing_run() {
+		assert_eq!(differences("[1, 2, 3, 4, 5]", "[5, 1, 2, 3, 4]", None), vec![
+			(Moved, path("/4"), path("/0")),
+		]);
+		assert_eq!(differences("[1, 2, 3, 4, 5]", "[2, 3, 4, 5, 1]", None), vec![
+			(Moved, path("/0"), path("/4")),
+		]);
+		assert_eq!(differences("[1, 2, 3, 4]", "[4, 3, 2, 1]", None).len(), 3);
+		assert_eq!(differences(r#"["a", "b", "c"]"#, r#"["c", "x", "a", "b"]"#, None), vec![
+			(Moved, path("/2"), path("/0")),
+			(Added, None, path("/1")),
+		]);
+	}
+
+	#[test]
+	fn longest_increasing_marks_one_longest_run() {
+		assert_eq!(longest_increasing(&[]), Vec::<bool>::new());
+		assert_eq!(longest_increasing(&[3, 1, 2]), vec![false, true, true]);
+		let marked = longest_increasing(&[0, 8, 4, 12, 2, 10, 6, 14, 1, 9, 5, 13, 3, 11, 7, 15]);
+		let run: Vec<usize> = [0, 8, 4, 12, 2, 10, 6, 14, 1, 9, 5, 13, 3, 11, 7, 15].iter().zip(&marked).filter(|(_, &m)| m).map(|(&v, _)| v).collect();
+		assert_eq!(run.len(), 6);
+		assert!(run.windows(2).all(|w| w[0] < w[1]));
+	}
+}
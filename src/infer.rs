@@ -0,0 +1,367 @@
+use crate::{JsonBuffer, JsonNode, JsonNumber, JsonVariant};
+
+const DRAFT: &str = "https://json-schema.org/draft/2020-12/schema";
+
+// Everything seen at one place in the document, across every array item
+// that shares it.
+#[derive(Default)]
+pub(crate) struct Shape {
+	pub(crate) nulls: usize,
+	pub(crate) bools: usize,
+	pub(crate) integers: usize,
+	pub(crate) fractions: usize,
+	pub(crate) minimum: Option<JsonNumber>,
+	pub(crate) maximum: Option<JsonNumber>,
+	pub(crate) strings: usize,
+	// The format every string so far has had, or None once they disagree.
+	pub(crate) format: Option<&'static str>,
+	pub(crate) arrays: usize,
+	pub(crate) items: Option<Box<Shape>>,
+	pub(crate) objects: usize,
+	// Members in the order they were first seen, each counting the objects
+	// that had it.
+	pub(crate) properties: Vec<(String, Shape)>,
+}
+
+impl Shape {
+	pub(crate) fn of(buffer: &JsonBuffer, node: usize) -> Shape {
+		let mut shape = Shape::default();
+		shape.add(buffer, node);
+		shape
+	}
+	pub(crate) fn count(&self) -> usize {
+		self.nulls + self.bools + self.integers + self.fractions + self.strings + self.arrays + self.objects
+	}
+	fn add(&mut self, buffer: &JsonBuffer, node: usize) {
+		match &buffer.nodes[node].variant {
+			JsonVariant::Null => self.nulls += 1,
+			JsonVariant::Bool(_) => self.bools += 1,
+			JsonVariant::Number(n) => {
+				if n.is_integer() {
+					self.integers += 1;
+				} else {
+					self.fractions += 1;
+				}
+				if self.minimum.as_ref().is_none_or(|minimum| n < minimum) {
+					self.minimum = Some(n.clone());
+				}
+				if self.maximum.as_ref().is_none_or(|maximum| n > maximum) {
+					self.maximum = Some(n.clone());
+				}
+			},
+			JsonVariant::String(s) => {
+				let format = string_format(s);
+				self.format = if self.strings == 0 {format} else {self.format.filter(|&seen| Some(seen) == format)};
+				self.strings += 1;
+			},
+			JsonVariant::Array(items) => {
+				self.arrays += 1;
+				let shape = self.items.get_or_insert_with(Box::default);
+				for &item in items {
+					shape.add(buffer, item);
+				}
+			},
+			JsonVariant::Object(entries) => {
+				self.objects += 1;
+				for &entry in entries {
+					if let JsonVariant::ObjectEntry(key, value) = &buffer.nodes[entry].variant {
+						let index = match self.properties.iter().position(|(k, _)| k==key) {
+							Some(index) => index,
+							None => {
+								self.properties.push((key.clone(), Shape::default()));
+								self.properties.len() - 1
+							},
+						};
+						self.properties[index].1.add(buffer, *value);
+					}
+				}
+			},
+			JsonVariant::ObjectEntry(_, value) => self.add(buffer, *value),
+		}
+	}
+}
+
+impl JsonBuffer {
+	// A schema that the document satisfies. Array items are described by one
+	// schema covering all of them, members missing from some of the objects
+	// at a place are left out of `required`, and numbers and strings get the
+	// range and format they were seen with.
+	pub fn infer_schema(&self) -> JsonBuffer {
+		let shape = Shape::of(self, 0);
+		let mut schema = JsonBuffer::new(vec![JsonNode {
+			variant: JsonVariant::Null,
+			parent: 0,
+			left: 0,
+			right: 0,
+		}]);
+		let draft = schema.push_node(JsonVariant::String(DRAFT.to_string()));
+		let root = schema.push_shape(&shape, vec![("$schema".to_string(), draft)]);
+		schema.replace_node(0, root);
+		schema
+	}
+	fn push_shape(&mut self, shape: &Shape, mut members: Vec<(String, usize)>) -> usize {
+		let numbers = shape.integers + shape.fractions;
+		let types: Vec<&str> = [
+			(shape.nulls, "null"),
+			(shape.bools, "boolean"),
+			(if shape.fractions == 0 {shape.integers} else {0}, "integer"),
+			(shape.fractions, "number"),
+			(shape.strings, "string"),
+			(shape.arrays, "array"),
+			(shape.objects, "object"),
+		].iter().filter(|(count, _)| *count > 0).map(|&(_, name)| name).collect();
+		let type_node = match types.as_slice() {
+			[] => None,
+			[name] => Some(self.push_node(JsonVariant::String(name.to_string()))),
+			names => {
+				let names = names.iter().map(|name| self.push_node(JsonVariant::String(name.to_string()))).collect();
+				Some(self.push_container(JsonVariant::Array(names)))
+			},
+		};
+		if let Some(type_node) = type_node {
+			members.push(("type".to_string(), type_node));
+		}
+		if numbers > 0 {
+			for (keyword, limit) in [("minimum", &shape.minimum), ("maximum", &shape.maximum)] {
+				if let Some(limit) = limit {
+					let node = self.push_node(JsonVariant::Number(limit.clone()));
+					members.push((keyword.to_string(), node));
+				}
+			}
+		}
+		if let (true, Some(format)) = (shape.strings > 0, shape.format) {
+			let node = self.push_node(JsonVariant::String(format.to_string()));
+			members.push(("format".to_string(), node));
+		}
+		if let Some(items) = shape.items.as_ref().filter(|items| items.count() > 0) {
+			let node = self.push_shape(items, Vec::new());
+			members.push(("items".to_string(), node));
+		}
+		if shape.objects > 0 {
+			let properties = shape.properties.iter()
+				.map(|(key, property)| (key.clone(), self.push_shape(property, Vec::new())))
+				.collect();
+			let node = self.push_members(properties);
+			members.push(("properties".to_string(), node));
+			let required: Vec<usize> = shape.properties.iter()
+				.filter(|(_, property)| property.count() == shape.objects)
+				.map(|(key, _)| self.push_node(JsonVariant::String(key.clone())))
+				.collect();
+			if !required.is_empty() {
+				let node = self.push_container(JsonVariant::Array(required));
+				members.push(("required".to_string(), node));
+			}
+		}
+		self.push_members(members)
+	}
+	fn push_members(&mut self, members: Vec<(String, usize)>) -> usize {
+		let entries = members.into_iter().map(|(key, value)| {
+			let entry = self.push_node(JsonVariant::ObjectEntry(key, value));
+			self.link_children(entry);
+			entry
+		}).collect();
+		self.push_container(JsonVariant::Object(entries))
+	}
+	fn push_container(&mut self, variant: JsonVariant) -> usize {
+		let node = self.push_node(variant);
+		self.link_children(node);
+		node
+	}
+}
+
+// The `format` a string looks like it has. Dates and times must also be in
+// range, so a schema never claims a format its strings don't have.
+fn string_format(s: &str) -> Option<&'static str> {
+	if is_date(s) {
+		Some("date")
+	} else if s.len() > 11 && s.as_bytes()[10].eq_ignore_ascii_case(&b't') && is_date(&s[..10]) && is_time(&s[11..]) {
+		Some("date-time")
+	} else if is_time(s) {
+		Some("time")
+	} else if is_uuid(s) {
+		Some("uuid")
+	} else if is_ipv4(s) {
+		Some("ipv4")
+	} else if is_email(s) {
+		Some("email")
+	} else if is_uri(s) {
+		Some("uri")
+	} else {
+		None
+	}
+}
+
+// Whether every byte of `s` is a digit, and there are `len` of them.
+fn digits(s: &str, len: usize) -> bool {
+	s.len() == len && s.bytes().all(|b| b.is_ascii_digit())
+}
+
+// Whether `s` is `len` digits making a number from `min` to `max`.
+fn in_range(s: &str, len: usize, min: u32, max: u32) -> bool {
+	digits(s, len) && s.parse().is_ok_and(|n: u32| min <= n && n <= max)
+}
+
+fn days_in_month(year: u32, month: u32) -> u32 {
+	match month {
+		2 if year.is_multiple_of(4) && (!year.is_multiple_of(100) || year.is_multiple_of(400)) => 29,
+		2 => 28,
+		4 | 6 | 9 | 11 => 30,
+		_ => 31,
+	}
+}
+
+// YYYY-MM-DD, naming a day that exists.
+fn is_date(s: &str) -> bool {
+	let parts: Vec<&str> = s.split('-').collect();
+	match parts.as_slice() {
+		[year, month, day] if digits(year, 4) && in_range(month, 2, 1, 12) && digits(day, 2) => {
+			let days = days_in_month(year.parse().unwrap(), month.parse().unwrap());
+			in_range(day, 2, 1, days)
+		},
+		_ => false,
+	}
+}
+
+// hh:mm:ss, with optional fractional seconds and then a zone of "Z" or an
+// offset. A second of 60 is a leap second.
+fn is_time(s: &str) -> bool {
+	let zone = s.rfind(['Z', 'z', '+', '-']);
+	let (time, zone) = match zone {
+		Some(i) => (&s[..i], &s[i..]),
+		None => return false,
+	};
+	let zone_ok = zone.eq_ignore_ascii_case("z") || {
+		let offset: Vec<&str> = zone[1..].split(':').collect();
+		matches!(offset.as_slice(), [hours, minutes] if in_range(hours, 2, 0, 23) && in_range(minutes, 2, 0, 59))
+	};
+	let (time, fraction) = match time.find('.') {
+		Some(i) => (&time[..i], &time[i + 1..]),
+		None => (time, "0"),
+	};
+	let parts: Vec<&str> = time.split(':').collect();
+	zone_ok
+		&& !fraction.is_empty() && fraction.bytes().all(|b| b.is_ascii_digit())
+		&& matches!(parts.as_slice(), [h, m, s] if in_range(h, 2, 0, 23) && in_range(m, 2, 0, 59) && in_range(s, 2, 0, 60))
+}
+
+fn is_uuid(s: &str) -> bool {
+	let groups: Vec<&str> = s.split('-').collect();
+	groups.len() == 5
+		&& groups.iter().zip(&[8, 4, 4, 4, 12]).all(|(group, &len)| group.len() == len && group.bytes().all(|b| b.is_ascii_hexdigit()))
+}
+
+fn is_ipv4(s: &str) -> bool {
+	let parts: Vec<&str> = s.split('.').collect();
+	parts.len() == 4 && parts.iter().all(|part| {
+		!part.is_empty() && part.len() <= 3 && part.bytes().all(|b| b.is_ascii_digit()) && part.parse::<u8>().is_ok()
+			&& (part.len() == 1 || !part.starts_with('0'))
+	})
+}
+
+fn is_email(s: &str) -> bool {
+	match s.split_once('@') {
+		Some((local, domain)) => {
+			!local.is_empty() && !domain.contains('@') && domain.contains('.')
+				&& !domain.starts_with('.') && !domain.ends_with('.') && !s.contains(char::is_whitespace)
+		},
+		None => false,
+	}
+}
+
+// A scheme followed by ':' and something, as in "https://example.com".
+fn is_uri(s: &str) -> bool {
+	match s.split_once("://") {
+		Some((scheme, rest)) => {
+			!rest.is_empty() && !s.contains(char::is_whitespace)
+				&& scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+				&& scheme.chars().all(|c| c.is_ascii_alphanumeric() || c=='+' || c=='-' || c=='.')
+		},
+		None => false,
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use serde_json::json;
+
+	fn infer(text: &str) -> serde_json::Value {
+		let mut schema = JsonBuffer::parse(text).unwrap().infer_schema().value_at(0);
+		schema.as_object_mut().unwrap().remove("$schema");
+		schema
+	}
+
+	#[test]
+	fn members_missing_from_some_items_are_optional() {
+		assert_eq!(infer(r#"[{"id": 1, "name": "a"}, {"id": 2}]"#), json!({
+			"type": "array",
+			"items": {
+				"type": "object",
+				"properties": {
+					"id": {"type": "integer", "minimum": 1, "maximum": 2},
+					"name": {"type": "string"},
+				},
+				"required": ["id"],
+			},
+		}));
+	}
+
+	#[test]
+	fn items_of_every_shape_are_merged() {
+		assert_eq!(infer(r#"[[1, "a"], [null, {"b": true}], []]"#), json!({
+			"type": "array",
+			"items": {
+				"type": "array",
+				"items": {
+					"type": ["null", "integer", "string", "object"],
+					"minimum": 1,
+					"maximum": 1,
+					"properties": {"b": {"type": "boolean"}},
+					"required": ["b"],
+				},
+			},
+		}));
+	}
+
+	#[test]
+	fn numbers_are_integers_until_one_has_a_fraction() {
+		assert_eq!(infer("[3, -20, 10]"), json!({"type": "array", "items": {"type": "integer", "minimum": -20, "maximum": 10}}));
+		assert_eq!(infer("[1e2, 2.0]")["items"]["type"], json!("integer"));
+		assert_eq!(infer("[3, 0.5, -1]"), json!({"type": "array", "items": {"type": "number", "minimum": -1, "maximum": 3}}));
+		// Whole numbers past an i64 are still compared exactly.
+		let schema = infer("[18446744073709551617, 18446744073709551616]");
+		assert_eq!(schema["items"]["minimum"].to_string(), "18446744073709551616");
+		assert_eq!(schema["items"]["maximum"].to_string(), "18446744073709551617");
+	}
+
+	#[test]
+	fn formats_are_only_given_to_strings_that_have_them() {
+		let formats = [
+			("2024-02-29", Some("date")),
+			("2024-01-31T23:59:60.5+05:30", Some("date-time")),
+			("00:00:00Z", Some("time")),
+			("123e4567-e89b-12d3-a456-426614174000", Some("uuid")),
+			("192.168.0.1", Some("ipv4")),
+			("someone@example.com", Some("email")),
+			("https://example.com/a", Some("uri")),
+			("2024-99-99", None),
+			("2023-02-29", None),
+			("2024-04-31", None),
+			("2024-00-10", None),
+			("99:99:99Z", None),
+			("24:00:00Z", None),
+			("12:00:00+24:00", None),
+			("2024-01-01T25:00:00Z", None),
+			("256.0.0.1", None),
+			("01.2.3.4", None),
+			("someone@localhost", None),
+			("example.com", None),
+		];
+		for (text, format) in formats {
+			assert_eq!(string_format(text), format, "{}", text);
+		}
+		// One string without the format leaves it out for the whole place.
+		assert_eq!(infer(r#"["2024-01-01", "2024-12-31"]"#)["items"]["format"], json!("date"));
+		assert_eq!(infer(r#"["2024-01-01", "2024-99-99"]"#)["items"].get("format"), None);
+	}
+}
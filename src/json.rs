@@ -0,0 +1,492 @@
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use serde::de::{self, Deserialize, DeserializeSeed, Deserializer, MapAccess, SeqAccess, Visitor};
+use serde::ser::{self, Serialize, SerializeMap, SerializeSeq, SerializeStruct, Serializer};
+
+use serde_json::Value;
+use serde_json::value::RawValue;
+
+use crate::{JsonBuffer, JsonNumber, JsonVariant};
+
+// The key serde_json's arbitrary precision numbers are passed on under, as a
+// map holding their text, when they aren't an i64 or u64 as written. It also
+// names the struct they're written as.
+const NUMBER_TOKEN: &str = "$serde_json::private::Number";
+// How deeply containers may nest, which is as deep as serde_json allows.
+const MAX_DEPTH: usize = 127;
+
+impl JsonBuffer {
+	// Parses straight into the arena rather than through serde_json::Value so
+	// object keys keep the order they were written in.
+	pub fn parse(text: &str) -> Result<JsonBuffer, String> {
+		let mut buffer = JsonBuffer::new(Vec::new());
+		let raw: &RawValue = serde_json::from_str(text).map_err(|err| err.to_string())?;
+		if buffer.push_raw(raw, 0).is_err() {
+			// Reading it as raw text doesn't limit nesting, which is all that
+			// can go wrong after, so serde_json is asked to say where it's too
+			// deep.
+			let err = serde_json::from_str::<Value>(text).err();
+			return Err(err.map_or_else(|| "recursion limit exceeded".to_string(), |err| err.to_string()));
+		}
+		Ok(buffer)
+	}
+	// Even with arbitrary precision, serde_json writes out the exponent of a
+	// number its own way, so each value is taken as raw text first and numbers
+	// keep exactly what was written. Containers are then read again from that
+	// text, `depth` of them deep.
+	fn push_raw(&mut self, raw: &RawValue, depth: usize) -> Result<usize, serde_json::Error> {
+		let text = raw.get();
+		if let Some(number) = JsonNumber::parse(text) {
+			return Ok(self.push_node(JsonVariant::Number(number)));
+		}
+		let mut deserializer = serde_json::Deserializer::from_str(text);
+		if text.starts_with(['[', '{']) {
+			if depth == MAX_DEPTH {
+				return Err(de::Error::custom("recursion limit exceeded"));
+			}
+			RawSeed { buffer: self, depth: depth + 1 }.deserialize(&mut deserializer)
+		} else {
+			NodeSeed { buffer: self }.deserialize(&mut deserializer)
+		}
+	}
+	pub fn open(path: &Path) -> Result<JsonBuffer, String> {
+		let text = fs::read_to_string(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+		let mut buffer = JsonBuffer::parse(&text).map_err(|err| format!("{}: {}", path.display(), err))?;
+		buffer.path = Some(path.to_path_buf());
+		Ok(buffer)
+	}
+	// The subtree at `index` as a value. Numbers keep their digits, though
+	// serde_json spells exponents its own way. An entry converts to its value.
+	pub fn value_at(&self, index: usize) -> Value {
+		match &self.nodes[index].variant {
+			JsonVariant::Null => Value::Null,
+			JsonVariant::Bool(b) => Value::Bool(*b),
+			JsonVariant::Number(n) => n.text.parse().map_or(Value::Null, Value::Number),
+			JsonVariant::String(s) => Value::String(s.clone()),
+			JsonVariant::ObjectEntry(_, child) => self.value_at(*child),
+			JsonVariant::Array(children) => Value::Array(children.iter().map(|&child| self.value_at(child)).collect()),
+			JsonVariant::Object(children) => Value::Object(children.iter().filter_map(|&child| match &self.nodes[child].variant {
+				JsonVariant::ObjectEntry(key, value) => Some((key.clone(), self.value_at(*value))),
+				_ => None,
+			}).collect()),
+		}
+	}
+	// Appends `value` to the arena as a detached subtree and returns its root.
+	// This doesn't go through NodeSeed, since serde_json gives numbers to a
+	// visitor as an f64 wherever that prints the same, which 1.0 doesn't.
+	pub(crate) fn push_value(&mut self, value: &Value) -> usize {
+		let variant = match value {
+			Value::Null => JsonVariant::Null,
+			Value::Bool(b) => JsonVariant::Bool(*b),
+			Value::Number(n) => JsonNumber::parse(&n.to_string()).map_or(JsonVariant::Null, JsonVariant::Number),
+			Value::String(s) => JsonVariant::String(s.clone()),
+			Value::Array(items) => {
+				let index = self.push_node(JsonVariant::Array(Vec::new()));
+				let children = items.iter().map(|item| self.push_value(item)).collect();
+				self.nodes[index].variant = JsonVariant::Array(children);
+				self.link_children(index);
+				return index;
+			},
+			Value::Object(members) => {
+				let index = self.push_node(JsonVariant::Object(Vec::new()));
+				let children = members.iter().map(|(key, value)| {
+					let entry = self.push_node(JsonVariant::ObjectEntry(key.clone(), 0));
+					let value = self.push_value(value);
+					self.nodes[entry].variant = JsonVariant::ObjectEntry(key.clone(), value);
+					self.link_children(entry);
+					entry
+				}).collect();
+				self.nodes[index].variant = JsonVariant::Object(children);
+				self.link_children(index);
+				return index;
+			},
+		};
+		self.push_node(variant)
+	}
+	// Laid out as serde_json's pretty printer does, but with each number as
+	// its text.
+	pub fn to_json_string(&self) -> String {
+		let mut text = String::new();
+		self.write_node(0, 0, &mut text);
+		text.push('\n');
+		text
+	}
+	fn write_node(&self, index: usize, depth: usize, text: &mut String) {
+		let quote = |s: &str| serde_json::to_string(s).unwrap_or_default();
+		match &self.nodes[index].variant {
+			JsonVariant::Null => text.push_str("null"),
+			JsonVariant::Bool(b) => text.push_str(if *b {"true"} else {"false"}),
+			JsonVariant::Number(n) => text.push_str(&n.text),
+			JsonVariant::String(s) => text.push_str(&quote(s)),
+			JsonVariant::ObjectEntry(key, child) => {
+				text.push_str(&quote(key));
+				text.push_str(": ");
+				self.write_node(*child, depth, text);
+			},
+			JsonVariant::Array(children) | JsonVariant::Object(children) => {
+				let (open, close) = match self.nodes[index].variant {
+					JsonVariant::Array(_) => ('[', ']'),
+					_ => ('{', '}'),
+				};
+				text.push(open);
+				for (i, &child) in children.iter().enumerate() {
+					text.push_str(if i == 0 {"\n"} else {",\n"});
+					text.push_str(&"  ".repeat(depth + 1));
+					self.write_node(child, depth + 1, text);
+				}
+				if !children.is_empty() {
+					text.push('\n');
+					text.push_str(&"  ".repeat(depth));
+				}
+				text.push(close);
+			},
+		}
+	}
+	// Writes the document to `path` and makes it the buffer's file.
+	pub fn save(&mut self, path: &Path) -> Result<(), String> {
+		fs::write(path, self.to_json_string()).map_err(|err| format!("{}: {}", path.display(), err))?;
+		self.path = Some(path.to_path_buf());
+		self.modified = false;
+		Ok(())
+	}
+}
+
+// The document, in any serde format.
+impl Serialize for JsonBuffer {
+	fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+		NodeRef { buffer: self, index: 0 }.serialize(serializer)
+	}
+}
+
+impl<'de> Deserialize<'de> for JsonBuffer {
+	fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<JsonBuffer, D::Error> {
+		let mut buffer = JsonBuffer::new(Vec::new());
+		NodeSeed { buffer: &mut buffer }.deserialize(deserializer)?;
+		Ok(buffer)
+	}
+}
+
+// Objects take the key order of the Value, which is sorted.
+impl From<&Value> for JsonBuffer {
+	fn from(value: &Value) -> JsonBuffer {
+		let mut buffer = JsonBuffer::new(Vec::new());
+		buffer.push_value(value);
+		buffer
+	}
+}
+
+impl From<Value> for JsonBuffer {
+	fn from(value: Value) -> JsonBuffer {
+		JsonBuffer::from(&value)
+	}
+}
+
+impl From<&JsonBuffer> for Value {
+	fn from(buffer: &JsonBuffer) -> Value {
+		buffer.value_at(0)
+	}
+}
+
+impl From<JsonBuffer> for Value {
+	fn from(buffer: JsonBuffer) -> Value {
+		buffer.value_at(0)
+	}
+}
+
+// Deserializes one value into new nodes at the end of the arena, producing
+// the index of its root. The first value read into an empty buffer becomes
+// the root at index 0.
+struct NodeSeed<'a> {
+	buffer: &'a mut JsonBuffer,
+}
+
+impl<'de, 'a> DeserializeSeed<'de> for NodeSeed<'a> {
+	type Value = usize;
+
+	fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<usize, D::Error> {
+		deserializer.deserialize_any(self)
+	}
+}
+
+impl<'de, 'a> Visitor<'de> for NodeSeed<'a> {
+	type Value = usize;
+
+	fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+		formatter.write_str("a JSON value")
+	}
+	fn visit_unit<E: de::Error>(self) -> Result<usize, E> {
+		Ok(self.buffer.push_node(JsonVariant::Null))
+	}
+	// Formats with an option type, unlike JSON, read null as None.
+	fn visit_none<E: de::Error>(self) -> Result<usize, E> {
+		self.visit_unit()
+	}
+	fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<usize, D::Error> {
+		self.deserialize(deserializer)
+	}
+	fn visit_bool<E: de::Error>(self, b: bool) -> Result<usize, E> {
+		Ok(self.buffer.push_node(JsonVariant::Bool(b)))
+	}
+	fn visit_i64<E: de::Error>(self, n: i64) -> Result<usize, E> {
+		Ok(self.buffer.push_node(JsonVariant::Number(JsonNumber::from(n))))
+	}
+	fn visit_u64<E: de::Error>(self, n: u64) -> Result<usize, E> {
+		Ok(self.buffer.push_node(JsonVariant::Number(JsonNumber::from(n))))
+	}
+	fn visit_i128<E: de::Error>(self, n: i128) -> Result<usize, E> {
+		Ok(self.buffer.push_node(JsonVariant::Number(JsonNumber::from(n))))
+	}
+	fn visit_u128<E: de::Error>(self, n: u128) -> Result<usize, E> {
+		Ok(self.buffer.push_node(JsonVariant::Number(JsonNumber::from(n))))
+	}
+	// NaN and the infinities, which other formats have, become null.
+	fn visit_f64<E: de::Error>(self, n: f64) -> Result<usize, E> {
+		let variant = JsonNumber::from_f64(n).map_or(JsonVariant::Null, JsonVariant::Number);
+		Ok(self.buffer.push_node(variant))
+	}
+	fn visit_str<E: de::Error>(self, s: &str) -> Result<usize, E> {
+		Ok(self.buffer.push_node(JsonVariant::String(s.to_string())))
+	}
+	fn visit_string<E: de::Error>(self, s: String) -> Result<usize, E> {
+		Ok(self.buffer.push_node(JsonVariant::String(s)))
+	}
+	fn visit_char<E: de::Error>(self, c: char) -> Result<usize, E> {
+		Ok(self.buffer.push_node(JsonVariant::String(c.to_string())))
+	}
+	// Bytes become an array of numbers, as serde_json writes them.
+	fn visit_bytes<E: de::Error>(self, bytes: &[u8]) -> Result<usize, E> {
+		let index = self.buffer.push_node(JsonVariant::Array(Vec::new()));
+		let children = bytes.iter().map(|&b| self.buffer.push_node(JsonVariant::Number(JsonNumber::from(b as u64)))).collect();
+		self.buffer.nodes[index].variant = JsonVariant::Array(children);
+		self.buffer.link_children(index);
+		Ok(index)
+	}
+	fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<usize, A::Error> {
+		let index = self.buffer.push_node(JsonVariant::Array(Vec::new()));
+		let mut children = Vec::new();
+		while let Some(child) = seq.next_element_seed(NodeSeed { buffer: &mut *self.buffer })? {
+			children.push(child);
+		}
+		self.buffer.nodes[index].variant = JsonVariant::Array(children);
+		self.buffer.link_children(index);
+		Ok(index)
+	}
+	fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<usize, A::Error> {
+		let mut next_key = map.next_key::<String>()?;
+		if next_key.as_deref() == Some(NUMBER_TOKEN) {
+			let text: String = map.next_value()?;
+			let number = JsonNumber::parse(&text).ok_or_else(|| de::Error::custom(format!("{} isn't a number", text)))?;
+			return Ok(self.buffer.push_node(JsonVariant::Number(number)));
+		}
+		let index = self.buffer.push_node(JsonVariant::Object(Vec::new()));
+		let mut children = Vec::new();
+		while let Some(key) = next_key {
+			let entry = self.buffer.push_node(JsonVariant::ObjectEntry(key.clone(), 0));
+			let value = map.next_value_seed(NodeSeed { buffer: &mut *self.buffer })?;
+			self.buffer.nodes[entry].variant = JsonVariant::ObjectEntry(key, value);
+			self.buffer.link_children(entry);
+			children.push(entry);
+			next_key = map.next_key()?;
+		}
+		self.buffer.nodes[index].variant = JsonVariant::Object(children);
+		self.buffer.link_children(index);
+		Ok(index)
+	}
+}
+
+// Reads a container of JSON text, passing each of its values to push_raw.
+struct RawSeed<'a> {
+	buffer: &'a mut JsonBuffer,
+	depth: usize,
+}
+
+impl<'de, 'a> DeserializeSeed<'de> for RawSeed<'a> {
+	type Value = usize;
+
+	fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<usize, D::Error> {
+		deserializer.deserialize_any(self)
+	}
+}
+
+impl<'de, 'a> Visitor<'de> for RawSeed<'a> {
+	type Value = usize;
+
+	fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+		formatter.write_str("a JSON array or object")
+	}
+	fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<usize, A::Error> {
+		let index = self.buffer.push_node(JsonVariant::Array(Vec::new()));
+		let mut children = Vec::new();
+		while let Some(raw) = seq.next_element::<&RawValue>()? {
+			children.push(self.buffer.push_raw(raw, self.depth).map_err(de::Error::custom)?);
+		}
+		self.buffer.nodes[index].variant = JsonVariant::Array(children);
+		self.buffer.link_children(index);
+		Ok(index)
+	}
+	fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<usize, A::Error> {
+		let index = self.buffer.push_node(JsonVariant::Object(Vec::new()));
+		let mut children = Vec::new();
+		while let Some(key) = map.next_key::<String>()? {
+			let entry = self.buffer.push_node(JsonVariant::ObjectEntry(key.clone(), 0));
+			let raw = map.next_value::<&RawValue>()?;
+			let value = self.buffer.push_raw(raw, self.depth).map_err(de::Error::custom)?;
+			self.buffer.nodes[entry].variant = JsonVariant::ObjectEntry(key, value);
+			self.buffer.link_children(entry);
+			children.push(entry);
+		}
+		self.buffer.nodes[index].variant = JsonVariant::Object(children);
+		self.buffer.link_children(index);
+		Ok(index)
+	}
+}
+
+// Serializes the subtree at `index`. An entry serializes as its value.
+struct NodeRef<'a> {
+	buffer: &'a JsonBuffer,
+	index: usize,
+}
+
+impl<'a> NodeRef<'a> {
+	fn at(&self, index: usize) -> NodeRef<'a> {
+		NodeRef {
+			buffer: self.buffer,
+			index,
+		}
+	}
+}
+
+impl<'a> Serialize for NodeRef<'a> {
+	fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+		match &self.buffer.nodes[self.index].variant {
+			JsonVariant::Null => serializer.serialize_unit(),
+			JsonVariant::Bool(b) => serializer.serialize_bool(*b),
+			JsonVariant::Number(n) => serialize_number(n, serializer),
+			JsonVariant::String(s) => serializer.serialize_str(s),
+			JsonVariant::ObjectEntry(_, child) => self.at(*child).serialize(serializer),
+			JsonVariant::Array(children) => {
+				let mut seq = serializer.serialize_seq(Some(children.len()))?;
+				for &child in children {
+					seq.serialize_element(&self.at(child))?;
+				}
+				seq.end()
+			},
+			JsonVariant::Object(children) => {
+				let mut map = serializer.serialize_map(Some(children.len()))?;
+				for &child in children {
+					if let JsonVariant::ObjectEntry(key, value) = &self.buffer.nodes[child].variant {
+						map.serialize_entry(key, &self.at(*value))?;
+					}
+				}
+				map.end()
+			},
+		}
+	}
+}
+
+// serde_json takes a number's text as it is, under its arbitrary precision
+// token. Other formats get integers whole, and anything else only if an f64
+// holds it exactly, rather than quietly losing digits.
+fn serialize_number<S: Serializer>(n: &JsonNumber, serializer: S) -> Result<S::Ok, S::Error> {
+	let name = std::any::type_name::<S>();
+	if name.trim_start_matches("&mut ").starts_with("serde_json::") {
+		let mut number = serializer.serialize_struct(NUMBER_TOKEN, 1)?;
+		number.serialize_field(NUMBER_TOKEN, &n.text)?;
+		return number.end();
+	}
+	if let Ok(i) = n.text.parse::<i64>() {
+		serializer.serialize_i64(i)
+	} else if let Ok(u) = n.text.parse::<u64>() {
+		serializer.serialize_u64(u)
+	} else if let Ok(i) = n.text.parse::<i128>() {
+		serializer.serialize_i128(i)
+	} else if let Ok(u) = n.text.parse::<u128>() {
+		serializer.serialize_u128(u)
+	} else if let Some(value) = n.exact_f64() {
+		serializer.serialize_f64(value)
+	} else {
+		Err(ser::Error::custom(format!("{} can't be written exactly", n)))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use serde::de::IntoDeserializer;
+	use serde::de::value::Error;
+	use serde_json::json;
+
+	use crate::patch::values_equal;
+
+	fn read<'de, D: Deserializer<'de, Error = Error>>(deserializer: D) -> JsonBuffer {
+		let mut buffer = JsonBuffer::new(Vec::new());
+		NodeSeed { buffer: &mut buffer }.deserialize(deserializer).unwrap();
+		buffer
+	}
+
+	#[test]
+	fn reads_values_json_doesnt_have() {
+		let buffer = read(i128::MIN.into_deserializer());
+		assert_eq!(buffer.to_json_string(), "-170141183460469231731687303715884105728\n");
+		let buffer = read(u128::MAX.into_deserializer());
+		assert_eq!(buffer.to_json_string(), "340282366920938463463374607431768211455\n");
+		assert_eq!(read('x'.into_deserializer()).value_at(0), json!("x"));
+		let mut buffer = JsonBuffer::new(Vec::new());
+		NodeSeed { buffer: &mut buffer }.visit_bytes::<Error>(&[0, 7, 255]).unwrap();
+		assert_eq!(buffer.value_at(0), json!([0, 7, 255]));
+		assert_eq!(read(f64::NAN.into_deserializer()).value_at(0), json!(null));
+	}
+
+	const NUMBERS: &[&str] = &["1.0", "1e3", "-0", "-0.0", "1E+2", "18446744073709551616", "-9223372036854775809", "1e400", "0.30000000000000000001"];
+
+	#[test]
+	fn numbers_keep_their_text() {
+		for &text in NUMBERS {
+			let buffer = JsonBuffer::parse(&format!("[{}]", text)).unwrap();
+			assert_eq!(buffer.to_json_string(), format!("[\n  {}\n]\n", text));
+			// serde_json spells exponents its own way, but nothing else changes.
+			let value = buffer.value_at(0);
+			let copy = JsonBuffer::from(&value);
+			assert!(values_equal(&copy.value_at(0), &value));
+			if !text.contains(['e', 'E']) {
+				assert_eq!(value.to_string(), format!("[{}]", text));
+				assert_eq!(copy.to_json_string(), buffer.to_json_string());
+			}
+		}
+		// Too large for an f64, but still a number.
+		assert!(matches!(&JsonBuffer::parse("1e400").unwrap().nodes[0].variant, JsonVariant::Number(n) if n.value.is_none()));
+	}
+
+	#[test]
+	fn serializing_keeps_numbers_exact() {
+		let buffer = JsonBuffer::parse("[18446744073709551617, 1e400, 2024, 1.0, -0, 0.1]").unwrap();
+		let text = serde_json::to_string(&buffer).unwrap();
+		assert_eq!(text, "[18446744073709551617,1e400,2024,1.0,-0,0.1]");
+		assert_eq!(serde_json::to_value(&buffer).unwrap(), Value::from(&buffer));
+		// Formats without arbitrary precision take what they can hold exactly.
+		let toml = |text| toml::to_string(&JsonBuffer::parse(text).unwrap()).map(|toml| toml.trim().to_string());
+		assert_eq!(toml(r#"{"a": 9223372036854775807, "b": 0.1, "c": 1e3}"#).unwrap(), "a = 9223372036854775807\nb = 0.1\nc = 1000.0");
+		assert!(toml(r#"{"a": 1e400}"#).is_err());
+		assert!(toml(r#"{"a": 0.30000000000000000001}"#).is_err());
+	}
+
+	#[test]
+	fn round_trips_through_toml() {
+		// Keys in an order TOML can write, with values before tables.
+		let text = r#"{
+			"title": "example",
+			"count": -3,
+			"ratio": 0.5,
+			"enabled": true,
+			"ports": [8000, 8001],
+			"owner": {"name": "Tom", "tags": ["a", "b"]},
+			"servers": [{"ip": "10.0.0.1"}, {"ip": "10.0.0.2"}]
+		}"#;
+		let buffer = JsonBuffer::parse(text).unwrap();
+		let toml = toml::to_string(&buffer).unwrap();
+		let read_back: JsonBuffer = toml::from_str(&toml).unwrap();
+		assert_eq!(read_back.to_json_string(), buffer.to_json_string());
+	}
+}
@@ -0,0 +1,336 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use skulpin::app::{InputState, VirtualKeyCode};
+
+use skulpin_test::JsonBufferMode;
+
+const DEFAULT_KEYMAP: &str = include_str!("../keymap.toml");
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Modifiers {
+	pub ctrl: bool,
+	pub shift: bool,
+	pub alt: bool,
+	pub logo: bool,
+}
+
+impl Modifiers {
+	pub fn from_state(input_state: &InputState) -> Modifiers {
+		let down = |left, right| input_state.is_key_down(left) || input_state.is_key_down(right);
+		Modifiers {
+			ctrl: down(VirtualKeyCode::LControl, VirtualKeyCode::RControl),
+			shift: down(VirtualKeyCode::LShift, VirtualKeyCode::RShift),
+			alt: down(VirtualKeyCode::LAlt, VirtualKeyCode::RAlt),
+			logo: down(VirtualKeyCode::LWin, VirtualKeyCode::RWin),
+		}
+	}
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Chord {
+	pub key: VirtualKeyCode,
+	pub modifiers: Modifiers,
+}
+
+impl Chord {
+	// Parses chords like "k", "shift+o" or "ctrl+alt+pagedown".
+	pub fn parse(text: &str) -> Result<Chord, String> {
+		let mut modifiers = Modifiers::default();
+		let mut parts: Vec<&str> = text.split('+').collect();
+		let key = parts.pop().unwrap_or("");
+		for part in parts {
+			match part.to_lowercase().as_str() {
+				"ctrl" | "control" => modifiers.ctrl = true,
+				"shift" => modifiers.shift = true,
+				"alt" => modifiers.alt = true,
+				"logo" | "super" | "cmd" => modifiers.logo = true,
+				_ => return Err(format!("unknown modifier \"{}\" in \"{}\"", part, text)),
+			}
+		}
+		let key = key_from_name(&key.to_lowercase())
+			.ok_or_else(|| format!("unknown key \"{}\" in \"{}\"", key, text))?;
+		Ok(Chord {
+			key,
+			modifiers,
+		})
+	}
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Command {
+	SelectUp,
+	SelectDown,
+	SelectParent,
+	SelectFirstChild,
+	SelectLastChild,
+	SelectAllChildren,
+	SelectDescendants,
+	SelectLeaves,
+	SelectAtDepth(usize),
+	SelectNextNode,
+	SelectPreviousNode,
+	SelectNextLeaf,
+	SelectPreviousLeaf,
+	SelectDocumentStart,
+	SelectDocumentEnd,
+	NextPrimary,
+	PreviousPrimary,
+	KeepPrimary,
+	DropPrimary,
+	NewDownSibling,
+	NewUpSibling,
+	NewFirstChild,
+	Objectify,
+	Stringify,
+	Delete,
+	Yank,
+	Put,
+	Wrap,
+	NormalMode,
+	InsertMode,
+	VisualMode,
+	CmdlineMode,
+	ConflictMode,
+	NextConflict,
+	PreviousConflict,
+	PickOurs,
+	PickTheirs,
+	PickBase,
+	NextCompletion,
+	PreviousCompletion,
+	AcceptCompletion,
+	ExtendUp,
+	ExtendDown,
+	ToggleFold,
+	OpenAllFolds,
+	CloseAllFolds,
+	FoldToDepth(usize),
+	PageDown,
+	PageUp,
+	Center,
+	ReloadTheme,
+	Quit,
+}
+
+impl Command {
+	// Commands are named in snake_case, followed by a number for those that
+	// take one, e.g. "select_at_depth 2".
+	pub fn parse(text: &str) -> Result<Command, String> {
+		let mut words = text.split_whitespace();
+		let name = words.next().unwrap_or("");
+		let argument = words.next();
+		if words.next().is_some() {
+			return Err(format!("too many arguments in \"{}\"", text));
+		}
+		let number = || {
+			argument
+				.ok_or_else(|| format!("{} needs a number", name))?
+				.parse::<usize>()
+				.map_err(|_| format!("{} needs a number", name))
+		};
+		let command = match name {
+			"select_at_depth" => return Ok(Command::SelectAtDepth(number()?)),
+			"fold_to_depth" => return Ok(Command::FoldToDepth(number()?)),
+			"select_up" => Command::SelectUp,
+			"select_down" => Command::SelectDown,
+			"select_parent" => Command::SelectParent,
+			"select_first_child" => Command::SelectFirstChild,
+			"select_last_child" => Command::SelectLastChild,
+			"select_all_children" => Command::SelectAllChildren,
+			"select_descendants" => Command::SelectDescendants,
+			"select_leaves" => Command::SelectLeaves,
+			"select_next_node" => Command::SelectNextNode,
+			"select_previous_node" => Command::SelectPreviousNode,
+			"select_next_leaf" => Command::SelectNextLeaf,
+			"select_previous_leaf" => Command::SelectPreviousLeaf,
+			"select_document_start" => Command::SelectDocumentStart,
+			"select_document_end" => Command::SelectDocumentEnd,
+			"next_primary" => Command::NextPrimary,
+			"previous_primary" => Command::PreviousPrimary,
+			"keep_primary" => Command::KeepPrimary,
+			"drop_primary" => Command::DropPrimary,
+			"new_down_sibling" => Command::NewDownSibling,
+			"new_up_sibling" => Command::NewUpSibling,
+			"new_first_child" => Command::NewFirstChild,
+			"objectify" => Command::Objectify,
+			"stringify" => Command::Stringify,
+			"delete" => Command::Delete,
+			"yank" => Command::Yank,
+			"put" => Command::Put,
+			"wrap" => Command::Wrap,
+			"normal_mode" => Command::NormalMode,
+			"insert_mode" => Command::InsertMode,
+			"visual_mode" => Command::VisualMode,
+			"cmdline_mode" => Command::CmdlineMode,
+			"conflict_mode" => Command::ConflictMode,
+			"next_conflict" => Command::NextConflict,
+			"previous_conflict" => Command::PreviousConflict,
+			"pick_ours" => Command::PickOurs,
+			"pick_theirs" => Command::PickTheirs,
+			"pick_base" => Command::PickBase,
+			"next_completion" => Command::NextCompletion,
+			"previous_completion" => Command::PreviousCompletion,
+			"accept_completion" => Command::AcceptCompletion,
+			"extend_up" => Command::ExtendUp,
+			"extend_down" => Command::ExtendDown,
+			"toggle_fold" => Command::ToggleFold,
+			"open_all_folds" => Command::OpenAllFolds,
+			"close_all_folds" => Command::CloseAllFolds,
+			"page_down" => Command::PageDown,
+			"page_up" => Command::PageUp,
+			"center" => Command::Center,
+			"reload_theme" => Command::ReloadTheme,
+			"quit" => Command::Quit,
+			_ => return Err(format!("unknown command \"{}\"", name)),
+		};
+		if argument.is_some() {
+			return Err(format!("{} doesn't take an argument", name));
+		}
+		Ok(command)
+	}
+}
+
+pub struct Keymap {
+	bindings: HashMap<JsonBufferMode, HashMap<Chord, Command>>,
+}
+
+// The built-in bindings. A test checks they parse, so they're only left out
+// if keymap.toml itself is broken.
+impl Default for Keymap {
+	fn default() -> Keymap {
+		let mut keymap = Keymap {
+			bindings: HashMap::new(),
+		};
+		if keymap.add_bindings(DEFAULT_KEYMAP).is_err() {
+			keymap.bindings.clear();
+		}
+		keymap
+	}
+}
+
+impl Keymap {
+	// The built-in bindings, with those in `path` (if any) layered on top.
+	pub fn load(path: Option<&Path>) -> Result<Keymap, String> {
+		let mut keymap = Keymap::default();
+		if let Some(path) = path {
+			let text = fs::read_to_string(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+			keymap.add_bindings(&text).map_err(|err| format!("{}: {}", path.display(), err))?;
+		}
+		Ok(keymap)
+	}
+	fn add_bindings(&mut self, text: &str) -> Result<(), String> {
+		let value: toml::Value = text.parse().map_err(|err: toml::de::Error| err.to_string())?;
+		let sections = value.as_table().ok_or("keymap must be a table of modes")?;
+		for (section, bindings) in sections {
+			let mode = match section.as_str() {
+				"normal" => JsonBufferMode::Normal,
+				"insert" => JsonBufferMode::Insert,
+				"visual" => JsonBufferMode::Visual,
+				"conflict" => JsonBufferMode::Conflict,
+				_ => return Err(format!("unknown mode \"{}\"", section)),
+			};
+			let bindings = bindings.as_table().ok_or_else(|| format!("[{}] must be a table", section))?;
+			let mode_bindings = self.bindings.entry(mode).or_default();
+			for (chord, command) in bindings {
+				let chord = Chord::parse(chord)?;
+				let command = command.as_str().ok_or_else(|| format!("binding for \"{}\" must be a string", section))?;
+				if command.is_empty() {
+					mode_bindings.remove(&chord);
+				} else {
+					mode_bindings.insert(chord, Command::parse(command)?);
+				}
+			}
+		}
+		Ok(())
+	}
+	// The command bound to a chord pressed this frame, matching modifiers
+	// exactly so "o" and "shift+o" can do different things.
+	pub fn command_for(&self, mode: JsonBufferMode, input_state: &InputState) -> Option<Command> {
+		let modifiers = Modifiers::from_state(input_state);
+		self.bindings.get(&mode)?
+			.iter()
+			.find(|(chord, _)| chord.modifiers == modifiers && input_state.is_key_just_down(chord.key))
+			.map(|(_, command)| command.clone())
+	}
+}
+
+fn key_from_name(name: &str) -> Option<VirtualKeyCode> {
+	let letters = [
+		VirtualKeyCode::A, VirtualKeyCode::B, VirtualKeyCode::C, VirtualKeyCode::D,
+		VirtualKeyCode::E, VirtualKeyCode::F, VirtualKeyCode::G, VirtualKeyCode::H,
+		VirtualKeyCode::I, VirtualKeyCode::J, VirtualKeyCode::K, VirtualKeyCode::L,
+		VirtualKeyCode::M, VirtualKeyCode::N, VirtualKeyCode::O, VirtualKeyCode::P,
+		VirtualKeyCode::Q, VirtualKeyCode::R, VirtualKeyCode::S, VirtualKeyCode::T,
+		VirtualKeyCode::U, VirtualKeyCode::V, VirtualKeyCode::W, VirtualKeyCode::X,
+		VirtualKeyCode::Y, VirtualKeyCode::Z,
+	];
+	let digits = [
+		VirtualKeyCode::Key0, VirtualKeyCode::Key1, VirtualKeyCode::Key2, VirtualKeyCode::Key3,
+		VirtualKeyCode::Key4, VirtualKeyCode::Key5, VirtualKeyCode::Key6, VirtualKeyCode::Key7,
+		VirtualKeyCode::Key8, VirtualKeyCode::Key9,
+	];
+	let function_keys = [
+		VirtualKeyCode::F1, VirtualKeyCode::F2, VirtualKeyCode::F3, VirtualKeyCode::F4,
+		VirtualKeyCode::F5, VirtualKeyCode::F6, VirtualKeyCode::F7, VirtualKeyCode::F8,
+		VirtualKeyCode::F9, VirtualKeyCode::F10, VirtualKeyCode::F11, VirtualKeyCode::F12,
+	];
+	let mut chars = name.chars();
+	if let (Some(c), None) = (chars.next(), chars.next()) {
+		if c.is_ascii_lowercase() {
+			return Some(letters[(c as u8 - b'a') as usize]);
+		} else if c.is_ascii_digit() {
+			return Some(digits[(c as u8 - b'0') as usize]);
+		}
+	}
+	if let Some(n) = name.strip_prefix('f').and_then(|n| n.parse::<usize>().ok()) {
+		return function_keys.get(n.wrapping_sub(1)).copied();
+	}
+	let key = match name {
+		"escape" | "esc" => VirtualKeyCode::Escape,
+		"return" | "enter" => VirtualKeyCode::Return,
+		"space" => VirtualKeyCode::Space,
+		"tab" => VirtualKeyCode::Tab,
+		"backspace" | "back" => VirtualKeyCode::Back,
+		"delete" => VirtualKeyCode::Delete,
+		"insert" => VirtualKeyCode::Insert,
+		"home" => VirtualKeyCode::Home,
+		"end" => VirtualKeyCode::End,
+		"pageup" => VirtualKeyCode::PageUp,
+		"pagedown" => VirtualKeyCode::PageDown,
+		"up" => VirtualKeyCode::Up,
+		"down" => VirtualKeyCode::Down,
+		"left" => VirtualKeyCode::Left,
+		"right" => VirtualKeyCode::Right,
+		"," | "comma" => VirtualKeyCode::Comma,
+		"." | "period" => VirtualKeyCode::Period,
+		"/" | "slash" => VirtualKeyCode::Slash,
+		"\\" | "backslash" => VirtualKeyCode::Backslash,
+		";" | "semicolon" => VirtualKeyCode::Semicolon,
+		":" | "colon" => VirtualKeyCode::Colon,
+		"'" | "apostrophe" => VirtualKeyCode::Apostrophe,
+		"`" | "grave" => VirtualKeyCode::Grave,
+		"[" | "lbracket" => VirtualKeyCode::LBracket,
+		"]" | "rbracket" => VirtualKeyCode::RBracket,
+		"-" | "minus" => VirtualKeyCode::Minus,
+		"=" | "equals" => VirtualKeyCode::Equals,
+		"plus" => VirtualKeyCode::Add,
+		_ => return None,
+	};
+	Some(key)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn built_in_bindings_parse() {
+		let mut keymap = Keymap {
+			bindings: HashMap::new(),
+		};
+		keymap.add_bindings(DEFAULT_KEYMAP).unwrap();
+		assert!(!Keymap::default().bindings[&JsonBufferMode::Normal].is_empty());
+	}
+}
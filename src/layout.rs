@@ -0,0 +1,367 @@
+// Where each token, selection and highlight of a buffer goes, worked out
+// without drawing anything, so the app only has to paint it.
+
+use std::collections::HashMap;
+
+use skulpin::skia_safe::{Point, Rect};
+
+use crate::{ChangeKind, JsonBuffer, JsonVariant};
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum TokenKind {
+	Key,
+	String,
+	Number,
+	Bool,
+	Null,
+	Punctuation,
+}
+
+impl TokenKind {
+	pub fn of(variant: &JsonVariant) -> TokenKind {
+		match variant {
+			JsonVariant::Null => TokenKind::Null,
+			JsonVariant::Bool(_) => TokenKind::Bool,
+			JsonVariant::Number(_) => TokenKind::Number,
+			JsonVariant::String(_) => TokenKind::String,
+			JsonVariant::ObjectEntry(_, _) => TokenKind::Key,
+			JsonVariant::Array(_) | JsonVariant::Object(_) => TokenKind::Punctuation,
+		}
+	}
+}
+
+// Sizes the layout is measured in, taken from the theme, and how many lines
+// the viewport shows.
+#[derive(Clone, Copy)]
+pub struct LayoutMetrics {
+	pub line_height: f32,
+	pub character_width: f32,
+	pub indent_width: usize,
+	pub visible_lines: usize,
+}
+
+// Everything needed to draw a frame of the buffer. It is only rebuilt when the
+// key changes, so idle frames just replay it.
+#[derive(PartialEq)]
+struct LayoutKey {
+	revision: u64,
+	scroll: usize,
+	selections: Vec<Vec<usize>>,
+	primary: usize,
+}
+
+// Why a node is drawn with a box behind it.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Highlight {
+	Change(ChangeKind),
+	Conflict,
+}
+
+#[derive(Default)]
+pub struct BufferLayout {
+	key: Option<LayoutKey>,
+	// Nodes to highlight, set by the app rather than taken from the buffer.
+	highlights: HashMap<usize, Highlight>,
+	pub segments: Vec<(Point, String, TokenKind)>,
+	pub selections: Vec<Option<Rect>>,
+	pub highlight_rects: Vec<(Rect, Highlight)>,
+	pub diagnostic_rects: Vec<Rect>,
+	pub primary: usize,
+}
+
+impl BufferLayout {
+	// Highlights aren't part of the key, so changing them drops the layout.
+	pub fn set_highlights(&mut self, highlights: HashMap<usize, Highlight>) {
+		if highlights != self.highlights {
+			self.highlights = highlights;
+			self.key = None;
+		}
+	}
+}
+
+impl JsonBuffer {
+	// Lays the buffer out again only if something it's drawn from changed.
+	pub fn update_layout(&self, metrics: &LayoutMetrics, layout: &mut BufferLayout) {
+		let key = LayoutKey {
+			revision: self.revision(),
+			scroll: self.scroll,
+			selections: self.selection_ranges(),
+			primary: self.primary,
+		};
+		if layout.key.as_ref() != Some(&key) {
+			self.lay_out(metrics, layout);
+			layout.key = Some(key);
+		}
+	}
+	// Lays out the lines in the viewport, whether or not anything changed.
+	pub fn lay_out(&self, metrics: &LayoutMetrics, layout: &mut BufferLayout) {
+		// Highlights are drawn like extra selections, numbered after the real ones.
+		// Conflicts come from the buffer itself, and win over the app's.
+		let mut highlights = layout.highlights.clone();
+		highlights.extend(self.unresolved_conflicts().map(|(_, conflict)| (conflict.node, Highlight::Conflict)));
+		let highlights: Vec<(usize, Highlight)> = highlights.into_iter().collect();
+		let highlight_of_node: HashMap<usize, usize> = highlights.iter()
+			.enumerate()
+			.map(|(i, &(node, _))| (node, self.selections.len() + i))
+			.collect();
+		// Nodes with a diagnostic get a slot each after those, for underlining.
+		let mut diagnosed: Vec<usize> = self.diagnostics().iter().map(|diagnostic| diagnostic.node).collect();
+		diagnosed.dedup();
+		let first_diagnostic = self.selections.len() + highlights.len();
+		let diagnostic_of_node: HashMap<usize, usize> = diagnosed.iter()
+			.enumerate()
+			.map(|(i, &node)| (node, first_diagnostic + i))
+			.collect();
+		let mut renderer = TextBufferRenderer::new(metrics.line_height, metrics.character_width, metrics.indent_width, first_diagnostic + diagnosed.len());
+
+		let selection_of_node: HashMap<usize, usize> = self.selection_ranges()
+			.into_iter()
+			.enumerate()
+			.flat_map(|(i, range)| range.into_iter().map(move |node| (node, i)))
+			.collect();
+
+		// Only subtrees with a line in the viewport are walked, so the cost of a
+		// frame doesn't grow with the size of the document.
+		let visible_lines = self.scroll..self.scroll + metrics.visible_lines;
+		let mut stack: Vec<(usize, bool, bool)> = vec![(0, false, false)];
+
+		while let Some((cur, visited, comma)) = stack.pop() {
+			let node = &self.nodes[cur];
+			let kind = TokenKind::of(&node.variant);
+			let node_selection_index = selection_of_node.get(&cur).copied();
+			let node_highlight_index = highlight_of_node.get(&cur).copied();
+			let node_diagnostic_index = diagnostic_of_node.get(&cur).copied();
+			if !visited {
+				renderer.goto_line(self.line_of(cur));
+				if let Some(nsi) = node_selection_index {
+					renderer.start_selection(nsi);
+				}
+				if let Some(nhi) = node_highlight_index {
+					renderer.start_selection(nhi);
+				}
+				if let Some(ndi) = node_diagnostic_index {
+					renderer.start_selection(ndi);
+				}
+			}
+			match &node.variant {
+				JsonVariant::Null => {
+					if !visited {
+						renderer.add_to_line("null", kind);
+						if comma {
+							renderer.add_to_line(",", TokenKind::Punctuation);
+						}
+						renderer.newline();
+						stack.push((cur, true, false));
+					}
+				},
+				JsonVariant::Bool(b) => {
+					if !visited {
+						renderer.add_to_line(b.to_string().as_str(), kind);
+						if comma {
+							renderer.add_to_line(",", TokenKind::Punctuation);
+						}renderer.newline();
+						stack.push((cur, true, false));
+					}
+				},
+				JsonVariant::Number(num) => {
+					if !visited {
+						renderer.add_to_line(num.text.as_str(), kind);
+						if comma {
+							renderer.add_to_line(",", TokenKind::Punctuation);
+						}
+						renderer.newline();
+						stack.push((cur, true, false));
+					}
+				},
+				JsonVariant::String(string) => {
+					if !visited {
+						renderer.add_to_line(format!("\"{}\"", string).as_str(), kind);
+						if comma {
+							renderer.add_to_line(",", TokenKind::Punctuation);
+						}
+						renderer.newline();
+						stack.push((cur, true, false));
+					}
+				},
+				JsonVariant::ObjectEntry(key, value) => {
+					if !visited {
+						renderer.add_to_line(format!("\"{}\"", key).as_str(), kind);
+						renderer.add_to_line(": ", TokenKind::Punctuation);
+						stack.push((cur, true, false));
+						stack.push((*value, false, comma));
+					}
+				},
+				JsonVariant::Array(children) if self.folded.contains(&cur) => {
+					if !visited {
+						let noun = if children.len() == 1 {"item"} else {"items"};
+						renderer.add_to_line(format!("[…] {} {}", children.len(), noun).as_str(), TokenKind::Punctuation);
+						if comma {
+							renderer.add_to_line(",", TokenKind::Punctuation);
+						}
+						renderer.newline();
+						stack.push((cur, true, false));
+					}
+				},
+				JsonVariant::Object(children) if self.folded.contains(&cur) => {
+					if !visited {
+						let noun = if children.len() == 1 {"key"} else {"keys"};
+						renderer.add_to_line(format!("{{…}} {} {}", children.len(), noun).as_str(), TokenKind::Punctuation);
+						if comma {
+							renderer.add_to_line(",", TokenKind::Punctuation);
+						}
+						renderer.newline();
+						stack.push((cur, true, false));
+					}
+				},
+				JsonVariant::Array(children) => {
+					if !visited {
+						renderer.add_to_line("[", TokenKind::Punctuation);
+						stack.push((cur, true, comma));
+						renderer.newline();
+						renderer.indent();
+						for child in self.children_in_lines(cur, visible_lines.clone()).iter().rev() {
+							stack.push((*child, false, Some(child) != children.last()));
+						}
+					} else {
+						renderer.goto_line(self.line_of(cur) + self.line_count(cur) - 1);
+						renderer.unindent();
+						renderer.add_to_line("]", TokenKind::Punctuation);
+						if comma {
+							renderer.add_to_line(",", TokenKind::Punctuation);
+						}
+						renderer.newline();
+					}
+				},
+				JsonVariant::Object(children) => {
+					if !visited {
+						renderer.add_to_line("{", TokenKind::Punctuation);
+						stack.push((cur, true, comma));
+						renderer.newline();
+						renderer.indent();
+						for child in self.children_in_lines(cur, visible_lines.clone()).iter().rev() {
+							stack.push((*child, false, Some(child) != children.last()));
+						}
+					} else {
+						renderer.goto_line(self.line_of(cur) + self.line_count(cur) - 1);
+						renderer.unindent();
+						renderer.add_to_line("}", TokenKind::Punctuation);
+						if comma {
+							renderer.add_to_line(",", TokenKind::Punctuation);
+						}
+						renderer.newline();
+					}
+				},
+			}
+			if visited {
+				if let Some(nsi) = node_selection_index {
+					renderer.end_selection(nsi);
+				}
+				if let Some(nhi) = node_highlight_index {
+					renderer.end_selection(nhi);
+				}
+				if let Some(ndi) = node_diagnostic_index {
+					renderer.end_selection(ndi);
+				}
+			}
+		}
+
+		layout.segments = renderer.segments;
+		layout.selections = renderer.selections;
+		layout.diagnostic_rects = layout.selections.split_off(first_diagnostic).into_iter().flatten().collect();
+		let highlight_rects = layout.selections.split_off(self.selections.len());
+		layout.highlight_rects = highlight_rects.into_iter()
+			.zip(highlights)
+			.filter_map(|(rect, (_, highlight))| Some((rect?, highlight)))
+			.collect();
+		layout.primary = self.primary;
+	}
+}
+
+fn rect_include_point(rect: &mut Rect, point: Point) {
+	if rect.left > point.x {
+		rect.left = point.x;
+	} else if rect.right < point.x {
+		rect.right = point.x;
+	}
+	if rect.top > point.y {
+		rect.top = point.y;
+	} else if rect.bottom < point.y {
+		rect.bottom = point.y;
+	}
+}
+
+// Lays out lines of text and the rectangles around selections, without
+// drawing anything.
+struct TextBufferRenderer {
+	indent: f32,
+	line_num: f32,
+	column: usize,
+	segments_so_far: Vec<(f32, String, TokenKind)>,
+	line_height: f32,
+	character_width: f32,
+	indent_width: f32,
+	segments: Vec<(Point, String, TokenKind)>,
+	selections: Vec<Option<Rect>>,
+	active_selections: Vec<usize>,
+}
+
+impl TextBufferRenderer {
+	fn new(line_height: f32, character_width: f32, indent_width: usize, num_selections: usize) -> TextBufferRenderer {
+		TextBufferRenderer {
+			indent: 0.,
+			line_num: 0.,
+			column: 0,
+			segments_so_far: Vec::new(),
+			line_height,
+			character_width,
+			indent_width: indent_width as f32,
+			segments: Vec::new(),
+			selections: (0..num_selections).map(|_| Option::None).collect(),
+			active_selections: Vec::new(),
+		}
+	}
+	fn add_to_selections(&mut self, point: Point) {
+		for index in &self.active_selections {
+			if let Some(Some(ref mut rect)) = self.selections.get_mut(*index) {
+				rect_include_point(rect, point);
+			} else {
+				self.selections[*index] = Option::Some(Rect::new(point.x, point.y, point.x, point.y));
+			}
+		}
+	}
+	fn add_to_line(&mut self, to_add: &str, kind: TokenKind) {
+		let indent = self.indent*self.character_width*self.indent_width;
+		let left = indent + (self.column as f32) * self.character_width;
+		self.segments_so_far.push((left, to_add.to_string(), kind));
+		self.column += to_add.chars().count();
+		let right = indent + (self.column as f32) * self.character_width;
+		let top = self.line_num*self.line_height;
+		let bottom = top + self.line_height;
+		self.add_to_selections(Point::new(left, top));
+		self.add_to_selections(Point::new(right, bottom));
+	}
+	fn start_selection(&mut self, index: usize) {
+		self.active_selections.push(index);
+	}
+	fn end_selection(&mut self, select: usize) {
+		let index = self.active_selections.iter().position(|&s| s==select).unwrap();
+		self.active_selections.remove(index);
+	}
+	fn newline(&mut self) {
+		let baseline = self.line_height*(self.line_num + 1.);
+		for (left, text, kind) in self.segments_so_far.drain(..) {
+			self.segments.push((Point::new(left, baseline), text, kind));
+		}
+		self.column = 0;
+		self.line_num += 1.;
+	}
+	fn goto_line(&mut self, line: usize) {
+		self.line_num = line as f32;
+	}
+	fn indent(&mut self) {
+		self.indent += 1.;
+	}
+	fn unindent(&mut self) {
+		self.indent -= 1.;
+	}
+}
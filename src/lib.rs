@@ -1,17 +1,48 @@
+mod codegen;
+mod completion;
+mod diff;
+mod infer;
+mod json;
+mod layout;
+mod merge;
+mod number;
+mod patch;
+mod pointer;
+mod schema;
+
+pub use completion::{Completion, CompletionAction};
+pub use diff::{ChangeKind, Difference};
+pub use layout::{BufferLayout, Highlight, LayoutMetrics, TokenKind};
+pub use merge::{Conflict, MergeSide};
+pub use number::JsonNumber;
+pub use patch::PatchOperation;
+pub use schema::{Diagnostic, Schema};
+
+use std::cell::{Ref, RefCell};
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
 use std::iter::once;
+use std::ops::Range;
+use std::path::PathBuf;
+use std::slice;
 
 use skulpin::app::AppDrawArgs;
 use skulpin::skia_safe::{Point, Rect};
 
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
 pub enum JsonBufferMode {
 	Normal,
 	Insert,
+	Visual,
+	Command,
+	Conflict,
 }
 
+#[derive(Clone)]
 pub enum JsonVariant {
 	Null,
 	Bool(bool),
-	Number(f64),
+	Number(JsonNumber),
 	String(String),
 	ObjectEntry(String, usize),
 	Array(Vec<usize>),
@@ -23,6 +54,7 @@ pub enum JsonInput {
 	Backspace,
 }
 
+#[derive(Clone)]
 pub struct JsonNode {
 	pub variant: JsonVariant,
 	pub parent: usize,
@@ -33,30 +65,143 @@ pub struct JsonNode {
 pub struct JsonBuffer {
 	pub nodes: Vec<JsonNode>,
 	pub selections: Vec<usize>,
+	pub primary: usize,
 	pub mode: JsonBufferMode,
+	// In visual mode, the fixed end of each selection's sibling range.
+	pub visual_anchors: Vec<usize>,
+	// Yanked subtrees, each stored as its own arena rooted at index 0.
+	pub register: Vec<Vec<JsonNode>>,
+	// Folds change the layout, so they're only changed through methods that
+	// touch the revision.
+	folded: HashSet<usize>,
+	// First line shown in the viewport.
+	pub scroll: usize,
+	pub path: Option<PathBuf>,
+	// Whether the document has been edited since it was loaded or saved.
+	pub modified: bool,
+	// Every edit since the document was loaded, as JSON Patch operations.
+	pub patch: Vec<PatchOperation>,
+	// Nodes a merge left for the user to decide between the two sides.
+	pub conflicts: Vec<Conflict>,
+	revision: u64,
+	layout: RefCell<LineLayout>,
+	validation: RefCell<schema::Validation>,
+}
+
+#[derive(Default)]
+struct LineLayout {
+	revision: u64,
+	counts: Vec<usize>,
+	starts: Vec<usize>,
 }
 
 impl JsonBuffer {
+	pub fn new(nodes: Vec<JsonNode>) -> JsonBuffer {
+		JsonBuffer {
+			nodes,
+			selections: vec![0],
+			primary: 0,
+			mode: JsonBufferMode::Normal,
+			visual_anchors: Vec::new(),
+			register: Vec::new(),
+			folded: HashSet::new(),
+			scroll: 0,
+			path: None,
+			modified: false,
+			patch: Vec::new(),
+			conflicts: Vec::new(),
+			revision: 0,
+			layout: RefCell::new(LineLayout::default()),
+			validation: RefCell::new(schema::Validation::default()),
+		}
+	}
+	pub fn primary_selection(&self) -> Option<usize> {
+		self.selections.get(self.primary).copied()
+	}
+	// Points `primary` back at `node` after the selection list was filtered,
+	// falling back to the first selection if it was removed.
+	fn restore_primary(&mut self, node: Option<usize>) {
+		self.primary = node
+			.and_then(|node| self.selections.iter().position(|&s| s==node))
+			.unwrap_or(0);
+	}
+	// Removes repeated selections, keeping the first occurrence of each node.
+	pub fn merge_selections(&mut self) {
+		let primary = self.primary_selection();
+		let mut seen = HashSet::new();
+		self.selections.retain(|index| seen.insert(*index));
+		self.restore_primary(primary);
+	}
+	// Structural edits replace or reshape whole subtrees, so a selection inside
+	// another selection would be edited twice or left pointing at a detached
+	// node. The outermost selection wins.
+	pub fn drop_nested_selections(&mut self) {
+		self.merge_selections();
+		let primary = self.primary_selection();
+		let selected: HashSet<usize> = self.selections.iter().copied().collect();
+		let nodes = &self.nodes;
+		self.selections.retain(|index| {
+			let mut cur = *index;
+			while nodes[cur].parent != cur {
+				cur = nodes[cur].parent;
+				if selected.contains(&cur) {
+					return false;
+				}
+			}
+			true
+		});
+		self.restore_primary(primary);
+	}
+	pub fn next_primary(&mut self) {
+		if !self.selections.is_empty() {
+			self.primary = (self.primary + 1) % self.selections.len();
+		}
+	}
+	pub fn previous_primary(&mut self) {
+		if !self.selections.is_empty() {
+			self.primary = (self.primary + self.selections.len() - 1) % self.selections.len();
+		}
+	}
+	pub fn keep_primary_selection(&mut self) {
+		if let Some(primary) = self.primary_selection() {
+			self.selections = vec![primary];
+			self.primary = 0;
+		}
+	}
+	pub fn drop_primary_selection(&mut self) {
+		if self.selections.len() > 1 {
+			self.selections.remove(self.primary);
+			if self.primary == self.selections.len() {
+				self.primary = 0;
+			}
+		}
+	}
 	pub fn select_up(&mut self) {
 		let new_selections = self.selections.iter().map(|index| {
 			self.nodes[*index].left
 		});
 		self.selections = new_selections.collect();
+		self.merge_selections();
 	}
 	pub fn select_down(&mut self) {
 		let new_selections = self.selections.iter().map(|index| {
 			self.nodes[*index].right
 		});
 		self.selections = new_selections.collect();
+		self.merge_selections();
 	}
 	pub fn select_parent(&mut self) {
 		let new_selections = self.selections.iter().map(|index| {
 			self.nodes[*index].parent
 		});
 		self.selections = new_selections.collect();
+		self.merge_selections();
 	}
 	pub fn select_first_child(&mut self) {
 		let new_selections = self.selections.iter().map(|index| {
+			if self.folded.contains(index) {
+				return *index;
+			}
 			match self.nodes[*index].variant {
 				JsonVariant::ObjectEntry(_, child) => {
 					child
@@ -79,23 +224,195 @@ impl JsonBuffer {
 			}
 		});
 		self.selections = new_selections.collect();
+		self.merge_selections();
 	}
 	pub fn select_all_children(&mut self) {
-		let new_selections = self.selections.iter().flat_map(|index: &usize| {
-			let iter: Box<dyn Iterator<Item=usize>> = match &self.nodes[*index].variant {
+		self.flat_map_selections(|buffer, index| {
+			match &buffer.nodes[index].variant {
 				JsonVariant::Null
 					| JsonVariant::Bool(_)
 					| JsonVariant::Number(_)
-					| JsonVariant::String(_) => Box::new(once(*index)),
-				JsonVariant::ObjectEntry(_, child) => Box::new(once(*child)),
-				JsonVariant::Array(children) => Box::new(children.iter().map(|c: &usize| *c)),
-				JsonVariant::Object(children) => Box::new(children.iter().map(|c: &usize| *c)),
-			};
-			iter
+					| JsonVariant::String(_) => vec![index],
+				_ if buffer.folded.contains(&index) => vec![index],
+				_ => buffer.children(index).to_vec(),
+			}
+		});
+	}
+	pub fn select_descendants(&mut self) {
+		self.flat_map_selections(|buffer, index| {
+			let mut descendants = buffer.visible_descendants(index);
+			if descendants.len() > 1 {
+				descendants.remove(0);
+			}
+			descendants
+		});
+	}
+	pub fn select_leaves(&mut self) {
+		self.flat_map_selections(|buffer, index| {
+			buffer.visible_descendants(index)
+				.into_iter()
+				.filter(|&node| buffer.visible_children(node).is_empty())
+				.collect()
+		});
+	}
+	// Depth counts arena levels, so an object's values sit two levels below it,
+	// one under their ObjectEntry.
+	pub fn select_at_depth(&mut self, depth: usize) {
+		self.flat_map_selections(|buffer, index| {
+			let mut level = vec![index];
+			for _ in 0..depth {
+				level = level.iter().flat_map(|&node| buffer.visible_children(node).iter().copied()).collect();
+			}
+			level
+		});
+	}
+	// Replaces each selection with the nodes inside it whose key or scalar
+	// value contains `query`. Selections with no matches are kept.
+	pub fn select_matching(&mut self, query: &str) {
+		self.flat_map_selections(|buffer, index| {
+			let matches: Vec<usize> = buffer.descendants(index)
+				.into_iter()
+				.filter(|&node| match &buffer.nodes[node].variant {
+					JsonVariant::ObjectEntry(key, _) | JsonVariant::String(key) => key.contains(query),
+					JsonVariant::Number(n) => n.text.contains(query),
+					JsonVariant::Bool(b) => b.to_string().contains(query),
+					JsonVariant::Null => "null".contains(query),
+					_ => false,
+				})
+				.collect();
+			if matches.is_empty() {vec![index]} else {matches}
+		});
+	}
+	// The subtree rooted at `index`, in the order the renderer draws it.
+	pub fn descendants(&self, index: usize) -> Vec<usize> {
+		let mut descendants = Vec::new();
+		let mut stack = vec![index];
+		while let Some(cur) = stack.pop() {
+			descendants.push(cur);
+			stack.extend(self.children(cur).iter().rev());
+		}
+		descendants
+	}
+	// Like `descendants`, but stopping at folded nodes.
+	fn visible_descendants(&self, index: usize) -> Vec<usize> {
+		let mut descendants = Vec::new();
+		let mut stack = vec![index];
+		while let Some(cur) = stack.pop() {
+			descendants.push(cur);
+			stack.extend(self.visible_children(cur).iter().rev());
+		}
+		descendants
+	}
+	// Replaces every selection with the nodes `f` returns for it, keeping the
+	// primary on the first node produced from the old primary.
+	fn flat_map_selections<F: Fn(&JsonBuffer, usize) -> Vec<usize>>(&mut self, f: F) {
+		let mut new_primary = 0;
+		let mut new_selections = Vec::new();
+		for (i, index) in self.selections.iter().enumerate() {
+			if i == self.primary {
+				new_primary = new_selections.len();
+			}
+			new_selections.extend(f(self, *index));
+		}
+		if !new_selections.is_empty() {
+			self.selections = new_selections;
+			self.primary = new_primary;
+			self.merge_selections();
+		}
+	}
+	pub fn children(&self, index: usize) -> &[usize] {
+		match &self.nodes[index].variant {
+			JsonVariant::ObjectEntry(_, child) => slice::from_ref(child),
+			JsonVariant::Array(children) | JsonVariant::Object(children) => children,
+			_ => &[],
+		}
+	}
+	// Folded nodes are leaves as far as navigation is concerned.
+	pub fn visible_children(&self, index: usize) -> &[usize] {
+		if self.folded.contains(&index) {
+			&[]
+		} else {
+			self.children(index)
+		}
+	}
+	fn last_descendant(&self, index: usize) -> usize {
+		let mut cur = index;
+		while let Some(&last) = self.visible_children(cur).last() {
+			cur = last;
+		}
+		cur
+	}
+	// Steps through nodes in the same pre-order the renderer draws them in.
+	pub fn next_in_order(&self, index: usize) -> Option<usize> {
+		if let Some(&first) = self.visible_children(index).first() {
+			return Some(first);
+		}
+		let mut cur = index;
+		while self.nodes[cur].parent != cur {
+			let node = &self.nodes[cur];
+			if node.right != node.parent {
+				return Some(node.right);
+			}
+			cur = node.parent;
+		}
+		None
+	}
+	pub fn previous_in_order(&self, index: usize) -> Option<usize> {
+		let node = &self.nodes[index];
+		if node.parent == index {
+			None
+		} else if node.left != node.parent {
+			Some(self.last_descendant(node.left))
+		} else {
+			Some(node.parent)
+		}
+	}
+	fn next_leaf(&self, index: usize) -> Option<usize> {
+		let mut cur = self.next_in_order(index)?;
+		while !self.visible_children(cur).is_empty() {
+			cur = self.next_in_order(cur)?;
+		}
+		Some(cur)
+	}
+	fn previous_leaf(&self, index: usize) -> Option<usize> {
+		let mut cur = self.previous_in_order(index)?;
+		while !self.visible_children(cur).is_empty() {
+			cur = self.previous_in_order(cur)?;
+		}
+		Some(cur)
+	}
+	fn map_selections<F: Fn(&JsonBuffer, usize) -> Option<usize>>(&mut self, f: F) {
+		let new_selections = self.selections.iter().map(|index| {
+			f(self, *index).unwrap_or(*index)
 		});
 		self.selections = new_selections.collect();
+		self.merge_selections();
+	}
+	pub fn select_next_node(&mut self) {
+		self.map_selections(JsonBuffer::next_in_order);
+	}
+	pub fn select_previous_node(&mut self) {
+		self.map_selections(JsonBuffer::previous_in_order);
+	}
+	pub fn select_next_leaf(&mut self) {
+		self.map_selections(JsonBuffer::next_leaf);
+	}
+	pub fn select_previous_leaf(&mut self) {
+		self.map_selections(JsonBuffer::previous_leaf);
+	}
+	pub fn select_last_child(&mut self) {
+		self.map_selections(|buffer, index| buffer.visible_children(index).last().copied());
+	}
+	pub fn select_document_start(&mut self) {
+		self.map_selections(|_, _| Some(0));
+	}
+	pub fn select_document_end(&mut self) {
+		self.map_selections(|buffer, _| Some(buffer.last_descendant(0)));
 	}
 	pub fn new_first_child(&mut self) {
+		self.edit();
+		self.drop_nested_selections();
+		let first_new = self.nodes.len();
 		let mut new_selections = Vec::with_capacity(self.selections.len());
 		for selection_index in self.selections.iter() {
 			let new_index = self.nodes.len();
@@ -155,9 +472,15 @@ impl JsonBuffer {
 				},
 			}
 		};
+		let added: Vec<usize> = new_selections.iter().copied().filter(|&index| index >= first_new).collect();
+		self.make_keys_unique(&added);
+		self.record_added(added);
 		self.selections = new_selections;
 	}
 	pub fn new_up_sibling(&mut self) {
+		self.edit();
+		self.drop_nested_selections();
+		let first_new = self.nodes.len();
 		let mut new_selections: Vec<usize> = Vec::with_capacity(self.selections.len());
 		for selection_index in self.selections.iter() {
 			let new_index = self.nodes.len();
@@ -215,9 +538,15 @@ impl JsonBuffer {
 				new_selections.push(*selection_index);
 			}
 		}
+		let added: Vec<usize> = new_selections.iter().copied().filter(|&index| index >= first_new).collect();
+		self.make_keys_unique(&added);
+		self.record_added(added);
 		self.selections = new_selections;
 	}
 	pub fn new_down_sibling(&mut self) {
+		self.edit();
+		self.drop_nested_selections();
+		let first_new = self.nodes.len();
 		let mut new_selections: Vec<usize> = Vec::with_capacity(self.selections.len());
 		for selection_index in self.selections.iter() {
 			let new_index = self.nodes.len();
@@ -275,9 +604,15 @@ impl JsonBuffer {
 				new_selections.push(*selection_index);
 			}
 		}
+		let added: Vec<usize> = new_selections.iter().copied().filter(|&index| index >= first_new).collect();
+		self.make_keys_unique(&added);
+		self.record_added(added);
 		self.selections = new_selections;
 	}
 	pub fn input(&mut self, input: JsonInput) {
+		self.edit();
+		self.merge_selections();
+		let old_pointers: Vec<String> = self.selections.iter().map(|&index| self.pointer_of(index)).collect();
 		for selection_index in self.selections.iter() {
 			match self.nodes[*selection_index].variant {
 				JsonVariant::String(ref mut string) => {
@@ -304,8 +639,18 @@ impl JsonBuffer {
 				},
 			}
 		}
+		for (index, from) in self.selections.clone().into_iter().zip(old_pointers) {
+			match self.nodes[index].variant {
+				JsonVariant::String(_) => self.record_replace(index),
+				JsonVariant::ObjectEntry(_, _) => self.record_rename(index, from),
+				_ => {},
+			}
+		}
 	}
 	pub fn objectify(&mut self) {
+		self.edit();
+		self.drop_nested_selections();
+		let mut entries = Vec::new();
 		for selection_index in self.selections.iter() {
 			match self.nodes[*selection_index].variant {
 				JsonVariant::Bool(_) | JsonVariant::String(_) | JsonVariant::Number(_) | JsonVariant::Null => {
@@ -328,13 +673,23 @@ impl JsonBuffer {
 							})
 						})
 						.unzip();
+					entries.extend(new_children.iter().copied());
 					self.nodes[*selection_index].variant = JsonVariant::Object(new_children);
 					self.nodes.append(&mut new_nodes);
 				},
 			}
 		}
+		self.make_keys_unique(&entries);
+		for index in self.selections.clone() {
+			if !matches!(self.nodes[index].variant, JsonVariant::ObjectEntry(_, _)) {
+				self.record_replace(index);
+			}
+		}
 	}
 	pub fn stringify(&mut self) {
+		self.edit();
+		self.drop_nested_selections();
+		let mut changed = Vec::new();
 		for selection_index in self.selections.iter() {
 			match self.nodes[*selection_index].variant {
 				JsonVariant::Null | JsonVariant::Array(_) | JsonVariant::Object(_) => {
@@ -343,11 +698,649 @@ impl JsonBuffer {
 				JsonVariant::Bool(b) => {
 					self.nodes[*selection_index].variant = JsonVariant::String(b.to_string());
 				},
-				JsonVariant::Number(n) => {
-					self.nodes[*selection_index].variant = JsonVariant::String(n.to_string());
+				JsonVariant::Number(ref n) => {
+					self.nodes[*selection_index].variant = JsonVariant::String(n.text.clone());
+				},
+				JsonVariant::String(_) | JsonVariant::ObjectEntry(_, _) => continue,
+			}
+			changed.push(*selection_index);
+		}
+		for index in changed {
+			self.record_replace(index);
+		}
+	}
+	// Rewrites the parent and sibling links of every child of `parent` from its
+	// variant, after the children list has been changed.
+	fn link_children(&mut self, parent: usize) {
+		let children = self.children(parent).to_vec();
+		if let JsonVariant::ObjectEntry(_, _) = self.nodes[parent].variant {
+			for child in children {
+				self.nodes[child].parent = parent;
+				self.nodes[child].left = parent;
+				self.nodes[child].right = parent;
+			}
+			return;
+		}
+		for (i, &child) in children.iter().enumerate() {
+			self.nodes[child].parent = parent;
+			self.nodes[child].left = if i == 0 {parent} else {children[i-1]};
+			self.nodes[child].right = if i+1 == children.len() {parent} else {children[i+1]};
+		}
+	}
+	// Renames each of `entries` whose key another member of its object already
+	// has, by adding the first free number to the key. Adding a member under a
+	// key that's taken replaces it, so the recorded patch would otherwise lose
+	// members the buffer still has.
+	fn make_keys_unique(&mut self, entries: &[usize]) {
+		let new: HashSet<usize> = entries.iter().copied().collect();
+		// For each object, the keys in use and the next number to try after each.
+		let mut objects: HashMap<usize, (HashSet<String>, HashMap<String, usize>)> = HashMap::new();
+		for &entry in entries {
+			if !matches!(self.nodes[entry].variant, JsonVariant::ObjectEntry(_, _)) {
+				continue;
+			}
+			let object = self.nodes[entry].parent;
+			let (taken, next) = objects.entry(object).or_insert_with(|| {
+				let taken = self.children(object).iter()
+					.filter(|child| !new.contains(child))
+					.filter_map(|&child| match &self.nodes[child].variant {
+						JsonVariant::ObjectEntry(key, _) => Some(key.clone()),
+						_ => None,
+					})
+					.collect();
+				(taken, HashMap::new())
+			});
+			if let JsonVariant::ObjectEntry(key, _) = &mut self.nodes[entry].variant {
+				if taken.contains(key) {
+					let n = next.entry(key.clone()).or_insert(1);
+					while taken.contains(&format!("{}{}", key, n)) {
+						*n += 1;
+					}
+					*key = format!("{}{}", key, n);
+				}
+				taken.insert(key.clone());
+			}
+		}
+	}
+	fn children_mut(&mut self, index: usize) -> Option<&mut Vec<usize>> {
+		match self.nodes[index].variant {
+			JsonVariant::Array(ref mut children) | JsonVariant::Object(ref mut children) => Some(children),
+			_ => None,
+		}
+	}
+	fn push_node(&mut self, variant: JsonVariant) -> usize {
+		let index = self.nodes.len();
+		self.nodes.push(JsonNode {
+			variant,
+			parent: index,
+			left: index,
+			right: index,
+		});
+		index
+	}
+	// Moves the root's contents into a new node and makes the root an array
+	// holding it, so the root can be wrapped while staying at index 0.
+	fn push_root_down(&mut self) -> usize {
+		let variant = std::mem::replace(&mut self.nodes[0].variant, JsonVariant::Null);
+		let moved = self.push_node(variant);
+		self.link_children(moved);
+		self.nodes[0].variant = JsonVariant::Array(vec![moved]);
+		self.link_children(0);
+		moved
+	}
+	pub fn copy_subtree(&self, index: usize) -> Vec<JsonNode> {
+		let order = self.descendants(index);
+		let new_index: HashMap<usize, usize> = order.iter().enumerate().map(|(i, &node)| (node, i)).collect();
+		let remap = |node: usize| new_index.get(&node).copied().unwrap_or(0);
+		order.iter().map(|&node| {
+			let node = &self.nodes[node];
+			let variant = match &node.variant {
+				JsonVariant::ObjectEntry(key, child) => JsonVariant::ObjectEntry(key.clone(), remap(*child)),
+				JsonVariant::Array(children) => JsonVariant::Array(children.iter().map(|&c| remap(c)).collect()),
+				JsonVariant::Object(children) => JsonVariant::Object(children.iter().map(|&c| remap(c)).collect()),
+				variant => variant.clone(),
+			};
+			JsonNode {
+				variant,
+				parent: remap(node.parent),
+				left: remap(node.left),
+				right: remap(node.right),
+			}
+		}).collect()
+	}
+	// Appends a copied subtree to the arena and returns its new root, which is
+	// left detached for the caller to place.
+	fn graft(&mut self, subtree: &[JsonNode]) -> usize {
+		let offset = self.nodes.len();
+		for node in subtree {
+			let variant = match &node.variant {
+				JsonVariant::ObjectEntry(key, child) => JsonVariant::ObjectEntry(key.clone(), child + offset),
+				JsonVariant::Array(children) => JsonVariant::Array(children.iter().map(|c| c + offset).collect()),
+				JsonVariant::Object(children) => JsonVariant::Object(children.iter().map(|c| c + offset).collect()),
+				variant => variant.clone(),
+			};
+			self.nodes.push(JsonNode {
+				variant,
+				parent: node.parent + offset,
+				left: node.left + offset,
+				right: node.right + offset,
+			});
+		}
+		offset
+	}
+	// Sorts the children of every selected container: objects by key, arrays
+	// by value with nulls, then bools, numbers and strings first. Containers
+	// inside an array keep their relative order, after the scalars. Member
+	// order means nothing to JSON Patch, so only arrays record operations.
+	pub fn sort(&mut self) {
+		self.edit();
+		for &selection in self.selections.clone().iter() {
+			let container = match self.nodes[selection].variant {
+				JsonVariant::ObjectEntry(_, child) => child,
+				_ => selection,
+			};
+			let mut children = self.children(container).to_vec();
+			match self.nodes[container].variant {
+				JsonVariant::Object(_) => {
+					children.sort_by(|&a, &b| self.entry_key(a).cmp(self.entry_key(b)));
+				},
+				JsonVariant::Array(_) => {
+					children.sort_by(|&a, &b| self.compare_scalars(a, b));
+					self.record_reorder(container, &children);
 				},
-				JsonVariant::String(_) | JsonVariant::ObjectEntry(_, _) => {},
+				_ => continue,
+			}
+			if let Some(old) = self.children_mut(container) {
+				*old = children;
 			}
+			self.link_children(container);
 		}
 	}
+	fn entry_key(&self, index: usize) -> &str {
+		match &self.nodes[index].variant {
+			JsonVariant::ObjectEntry(key, _) => key,
+			_ => "",
+		}
+	}
+	fn compare_scalars(&self, a: usize, b: usize) -> Ordering {
+		let rank = |variant: &JsonVariant| match variant {
+			JsonVariant::Null => 0,
+			JsonVariant::Bool(_) => 1,
+			JsonVariant::Number(_) => 2,
+			JsonVariant::String(_) => 3,
+			_ => 4,
+		};
+		let (a, b) = (&self.nodes[a].variant, &self.nodes[b].variant);
+		match (a, b) {
+			(JsonVariant::Bool(a), JsonVariant::Bool(b)) => a.cmp(b),
+			(JsonVariant::Number(a), JsonVariant::Number(b)) => a.partial_cmp(b).unwrap_or(Ordering::Equal),
+			(JsonVariant::String(a), JsonVariant::String(b)) => a.cmp(b),
+			_ => rank(a).cmp(&rank(b)),
+		}
+	}
+	// Each selection paired with the siblings it covers: just itself in normal
+	// mode, or the run between its anchor and itself in visual mode.
+	pub fn selection_ranges(&self) -> Vec<Vec<usize>> {
+		match self.mode {
+			JsonBufferMode::Visual => {
+				self.selections.iter().zip(self.visual_anchors.iter()).map(|(&head, &anchor)| {
+					let parent = self.nodes[head].parent;
+					let siblings = self.children(parent);
+					match (siblings.iter().position(|&s| s==head), siblings.iter().position(|&s| s==anchor)) {
+						(Some(a), Some(b)) if parent != head => siblings[a.min(b)..=a.max(b)].to_vec(),
+						_ => vec![head],
+					}
+				}).collect()
+			},
+			_ => self.selections.iter().map(|&s| vec![s]).collect(),
+		}
+	}
+	pub fn enter_visual(&mut self) {
+		self.drop_nested_selections();
+		self.visual_anchors = self.selections.clone();
+		self.mode = JsonBufferMode::Visual;
+	}
+	pub fn exit_visual(&mut self) {
+		self.visual_anchors.clear();
+		self.mode = JsonBufferMode::Normal;
+		self.merge_selections();
+	}
+	// Selects every node of every visual range and returns to normal mode, so
+	// operators can run on the ranges as ordinary selections.
+	fn expand_visual_ranges(&mut self) {
+		if let JsonBufferMode::Visual = self.mode {
+			let ranges = self.selection_ranges();
+			self.selections = Vec::new();
+			self.visual_anchors = Vec::new();
+			for (i, range) in ranges.into_iter().enumerate() {
+				if i == self.primary {
+					self.primary = self.selections.len();
+				}
+				self.selections.extend(range);
+			}
+			self.mode = JsonBufferMode::Normal;
+			self.merge_selections();
+		}
+	}
+	pub fn extend_down(&mut self) {
+		for head in self.selections.iter_mut() {
+			let node = &self.nodes[*head];
+			if node.right != node.parent {
+				*head = node.right;
+			}
+		}
+	}
+	pub fn extend_up(&mut self) {
+		for head in self.selections.iter_mut() {
+			let node = &self.nodes[*head];
+			if node.left != node.parent {
+				*head = node.left;
+			}
+		}
+	}
+	// Deleting the root leaves it as null, and deleting an object's value
+	// deletes its whole entry.
+	pub fn delete(&mut self) {
+		self.edit();
+		self.expand_visual_ranges();
+		self.drop_nested_selections();
+		let mut holes = Vec::new();
+		for selection_index in self.selections.clone() {
+			let mut target = selection_index;
+			let mut parent = self.nodes[target].parent;
+			if let JsonVariant::ObjectEntry(_, _) = self.nodes[parent].variant {
+				target = parent;
+				parent = self.nodes[target].parent;
+			}
+			if parent == target {
+				self.nodes[target].variant = JsonVariant::Null;
+				self.record_replace(target);
+				holes.push((target, 0));
+				continue;
+			}
+			self.record_remove(target);
+			if let Some(children) = self.children_mut(parent) {
+				if let Some(position) = children.iter().position(|&c| c==target) {
+					children.remove(position);
+					holes.push((parent, position));
+				}
+			}
+			self.link_children(parent);
+		}
+		self.selections = holes.into_iter().map(|(parent, position)| {
+			let children = self.children(parent);
+			if children.is_empty() {
+				parent
+			} else {
+				children[position.min(children.len() - 1)]
+			}
+		}).collect();
+		self.merge_selections();
+	}
+	pub fn yank(&mut self) {
+		self.expand_visual_ranges();
+		self.drop_nested_selections();
+		self.register = self.selections.iter().map(|&s| self.copy_subtree(s)).collect();
+	}
+	// Pastes the register after each selection, converting between object
+	// entries and plain values to suit the parent.
+	pub fn put(&mut self) {
+		self.edit();
+		self.drop_nested_selections();
+		let register = self.register.clone();
+		let mut new_selections = Vec::with_capacity(self.selections.len());
+		for selection_index in self.selections.clone() {
+			let parent = self.nodes[selection_index].parent;
+			// The root has no siblings to paste beside, so it's left as it is.
+			let position = self.children(parent).iter().position(|&c| c==selection_index);
+			let (in_object, position) = match (&self.nodes[parent].variant, position) {
+				(JsonVariant::Object(_), Some(position)) if parent != selection_index => (true, position + 1),
+				(JsonVariant::Array(_), Some(position)) if parent != selection_index => (false, position + 1),
+				_ => {
+					new_selections.push(selection_index);
+					continue;
+				},
+			};
+			let mut pasted = Vec::with_capacity(register.len());
+			for subtree in register.iter() {
+				let mut root = self.graft(subtree);
+				let is_entry = matches!(self.nodes[root].variant, JsonVariant::ObjectEntry(_, _));
+				if in_object && !is_entry {
+					let entry = self.push_node(JsonVariant::ObjectEntry("".to_string(), root));
+					self.link_children(entry);
+					root = entry;
+				} else if !in_object && is_entry {
+					root = self.children(root)[0];
+				}
+				pasted.push(root);
+			}
+			if let Some(children) = self.children_mut(parent) {
+				children.splice(position..position, pasted.iter().copied());
+			}
+			self.link_children(parent);
+			self.make_keys_unique(&pasted);
+			self.record_added(pasted.clone());
+			new_selections.extend(pasted);
+		}
+		self.selections = new_selections;
+		self.merge_selections();
+	}
+	// Replaces each range of siblings with a single new container holding them.
+	// With `as_object`, ranges of array items become an object keyed by "", "1",
+	// "2" and so on.
+	fn wrap_ranges(&mut self, ranges: Vec<Vec<usize>>, as_object: bool) {
+		self.edit();
+		let mut new_selections = Vec::with_capacity(ranges.len());
+		for range in ranges {
+			// Pushing the root down already wraps it in an array.
+			if range == [0] {
+				let moved = self.push_root_down();
+				if as_object {
+					let entry = self.push_node(JsonVariant::ObjectEntry("".to_string(), moved));
+					self.link_children(entry);
+					self.nodes[0].variant = JsonVariant::Object(vec![entry]);
+					self.link_children(0);
+				}
+				self.record_replace(0);
+				new_selections.push(0);
+				continue;
+			}
+			let parent = self.nodes[range[0]].parent;
+			// Overlapping visual ranges may already have been wrapped.
+			if range.iter().any(|node| !self.children(parent).contains(node)) {
+				continue;
+			}
+			// The range is removed last to first so each pointer is still right
+			// when its removal is applied.
+			for &node in range.iter().rev() {
+				self.record_remove(node);
+			}
+			let wrapper = match self.nodes[parent].variant {
+				JsonVariant::Object(_) => {
+					let inner = self.push_node(JsonVariant::Object(range.clone()));
+					self.link_children(inner);
+					let entry = self.push_node(JsonVariant::ObjectEntry("".to_string(), inner));
+					self.link_children(entry);
+					entry
+				},
+				_ if as_object => {
+					let entries: Vec<usize> = range.iter().map(|&item| {
+						let entry = self.push_node(JsonVariant::ObjectEntry("".to_string(), item));
+						self.link_children(entry);
+						entry
+					}).collect();
+					let inner = self.push_node(JsonVariant::Object(entries.clone()));
+					self.link_children(inner);
+					self.make_keys_unique(&entries);
+					inner
+				},
+				_ => {
+					let inner = self.push_node(JsonVariant::Array(range.clone()));
+					self.link_children(inner);
+					inner
+				},
+			};
+			match self.nodes[parent].variant {
+				JsonVariant::ObjectEntry(_, ref mut child) => {
+					*child = wrapper;
+				},
+				JsonVariant::Array(ref mut children) | JsonVariant::Object(ref mut children) => {
+					let position = children.iter().position(|&c| c==range[0]).unwrap();
+					children.splice(position..position+range.len(), once(wrapper));
+				},
+				_ => {},
+			}
+			self.link_children(parent);
+			self.make_keys_unique(&[wrapper]);
+			self.record_added(vec![wrapper]);
+			new_selections.push(wrapper);
+		}
+		self.selections = new_selections;
+		self.merge_selections();
+	}
+	pub fn wrap(&mut self) {
+		if let JsonBufferMode::Normal = self.mode {
+			self.drop_nested_selections();
+		}
+		let ranges = self.selection_ranges();
+		self.exit_visual();
+		self.wrap_ranges(ranges, false);
+	}
+	pub fn visual_objectify(&mut self) {
+		let ranges = self.selection_ranges();
+		self.exit_visual();
+		self.wrap_ranges(ranges, true);
+	}
+	fn is_container(&self, index: usize) -> bool {
+		matches!(self.nodes[index].variant, JsonVariant::Array(_) | JsonVariant::Object(_))
+	}
+	// An entry folds and unfolds along with its value.
+	fn fold_target(&self, index: usize) -> usize {
+		match self.nodes[index].variant {
+			JsonVariant::ObjectEntry(_, child) => child,
+			_ => index,
+		}
+	}
+	// Moves selections that ended up inside a folded node onto the outermost
+	// folded node hiding them.
+	fn lift_hidden_selections(&mut self) {
+		let new_selections = self.selections.iter().map(|&index| {
+			let mut visible = index;
+			let mut cur = index;
+			while self.nodes[cur].parent != cur {
+				cur = self.nodes[cur].parent;
+				if self.folded.contains(&cur) {
+					visible = cur;
+				}
+			}
+			visible
+		});
+		self.selections = new_selections.collect();
+		self.merge_selections();
+	}
+	// Folds the container at `index`, or the value of the entry at `index`.
+	pub fn fold(&mut self, index: usize) {
+		let target = self.fold_target(index);
+		if self.is_container(target) && self.folded.insert(target) {
+			self.touch();
+			self.lift_hidden_selections();
+		}
+	}
+	pub fn unfold(&mut self, index: usize) {
+		if self.folded.remove(&self.fold_target(index)) {
+			self.touch();
+		}
+	}
+	pub fn is_folded(&self, index: usize) -> bool {
+		self.folded.contains(&self.fold_target(index))
+	}
+	pub fn toggle_fold(&mut self) {
+		self.touch();
+		for &selection_index in self.selections.iter() {
+			let target = self.fold_target(selection_index);
+			if !self.folded.remove(&target) && self.is_container(target) {
+				self.folded.insert(target);
+			}
+		}
+		self.lift_hidden_selections();
+	}
+	pub fn open_all_folds(&mut self) {
+		self.touch();
+		self.folded.clear();
+	}
+	pub fn close_all_folds(&mut self) {
+		self.fold_to_depth(0);
+	}
+	// Folds every container nested `depth` or more containers deep, counting the
+	// root as depth 0, and opens the ones above it.
+	pub fn fold_to_depth(&mut self, depth: usize) {
+		self.touch();
+		self.folded.clear();
+		let mut stack = vec![(0, 0)];
+		while let Some((cur, cur_depth)) = stack.pop() {
+			let child_depth = if self.is_container(cur) {
+				if cur_depth >= depth {
+					self.folded.insert(cur);
+				}
+				cur_depth + 1
+			} else {
+				cur_depth
+			};
+			stack.extend(self.children(cur).iter().map(|&child| (child, child_depth)));
+		}
+		self.lift_hidden_selections();
+	}
+	pub fn revision(&self) -> u64 {
+		self.revision
+	}
+	// Called by every method that changes what the renderer would draw, other
+	// than the selections.
+	fn touch(&mut self) {
+		self.revision += 1;
+	}
+	// Called by every method that changes the document itself.
+	fn edit(&mut self) {
+		self.touch();
+		self.modified = true;
+	}
+	// Recomputes every node's line count and first line in one pass if the
+	// buffer has changed since they were last cached.
+	fn layout(&self) -> Ref<'_, LineLayout> {
+		let stale = {
+			let layout = self.layout.borrow();
+			layout.revision != self.revision || layout.counts.len() != self.nodes.len()
+		};
+		if stale {
+			let order = self.descendants(0);
+			let mut counts = vec![0; self.nodes.len()];
+			for &index in order.iter().rev() {
+				counts[index] = match self.nodes[index].variant {
+					JsonVariant::ObjectEntry(_, child) => counts[child],
+					JsonVariant::Array(ref children) | JsonVariant::Object(ref children) if !self.folded.contains(&index) => {
+						2 + children.iter().map(|&child| counts[child]).sum::<usize>()
+					},
+					_ => 1,
+				};
+			}
+			let mut starts = vec![0; self.nodes.len()];
+			for &index in order.iter() {
+				let mut line = starts[index];
+				if self.is_container(index) {
+					line += 1;
+				}
+				for &child in self.children(index) {
+					starts[child] = line;
+					line += counts[child];
+				}
+			}
+			*self.layout.borrow_mut() = LineLayout {
+				revision: self.revision,
+				counts,
+				starts,
+			};
+		}
+		self.layout.borrow()
+	}
+	// Number of lines the renderer uses to draw the node.
+	pub fn line_count(&self, index: usize) -> usize {
+		self.layout().counts[index]
+	}
+	// Line the node starts on. An entry's value shares its entry's line.
+	pub fn line_of(&self, index: usize) -> usize {
+		self.layout().starts[index]
+	}
+	// The children of `index` that have at least one line in `lines`, found by
+	// binary search so huge containers cost nothing to skip past.
+	pub fn children_in_lines(&self, index: usize, lines: Range<usize>) -> &[usize] {
+		let children = self.visible_children(index);
+		let layout = self.layout();
+		let first = children.partition_point(|&child| layout.starts[child] + layout.counts[child] <= lines.start);
+		let last = children.partition_point(|&child| layout.starts[child] < lines.end);
+		&children[first..last.max(first)]
+	}
+	// Scrolls as little as possible to bring the primary selection into a
+	// viewport `visible_lines` tall.
+	pub fn scroll_to_primary(&mut self, visible_lines: usize) {
+		if let Some(primary) = self.primary_selection() {
+			let line = self.line_of(primary);
+			if line < self.scroll {
+				self.scroll = line;
+			} else if line >= self.scroll + visible_lines {
+				self.scroll = line + 1 - visible_lines;
+			}
+		}
+	}
+	pub fn center_primary(&mut self, visible_lines: usize) {
+		if let Some(primary) = self.primary_selection() {
+			self.scroll = self.line_of(primary).saturating_sub(visible_lines / 2);
+		}
+	}
+	pub fn scroll_by(&mut self, lines: isize) {
+		let last_line = self.line_count(0).saturating_sub(1);
+		if lines < 0 {
+			self.scroll = self.scroll.saturating_sub(lines.unsigned_abs());
+		} else {
+			self.scroll = (self.scroll + lines as usize).min(last_line);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn put_on_root_leaves_it_selected() {
+		let mut buffer = JsonBuffer::parse("[1,2]").unwrap();
+		buffer.selections = vec![buffer.children(0)[0]];
+		buffer.yank();
+		buffer.select_document_start();
+		buffer.put();
+		assert_eq!(buffer.selections, vec![0]);
+		assert_eq!(buffer.value_at(0), serde_json::json!([1, 2]));
+	}
+
+	#[test]
+	fn selecting_inside_a_fold_stops_at_it() {
+		let mut buffer = JsonBuffer::parse(r#"{"a": [1, [2]], "b": 3}"#).unwrap();
+		let a = buffer.resolve("/a").unwrap();
+		buffer.fold(a);
+		let b = buffer.resolve("/b").unwrap();
+		let entries = buffer.children(0).to_vec();
+
+		buffer.select_leaves();
+		assert_eq!(buffer.selections, vec![a, b]);
+		buffer.selections = vec![0];
+		buffer.select_descendants();
+		assert_eq!(buffer.selections, vec![entries[0], a, entries[1], b]);
+		buffer.selections = vec![0];
+		buffer.select_at_depth(3);
+		assert_eq!(buffer.selections, vec![0]);
+		buffer.selections = vec![a];
+		buffer.select_all_children();
+		assert_eq!(buffer.selections, vec![a]);
+	}
+
+	#[test]
+	fn close_all_folds_folds_every_container() {
+		let mut buffer = JsonBuffer::parse(r#"{"a": [1, [2]], "b": {}}"#).unwrap();
+		buffer.close_all_folds();
+		let containers = ["", "/a", "/a/1", "/b"].map(|pointer| buffer.resolve(pointer).unwrap());
+		assert!(containers.iter().all(|&node| buffer.is_folded(node)));
+		buffer.fold_to_depth(1);
+		assert!(!buffer.is_folded(containers[0]));
+		assert!(containers[1..].iter().all(|&node| buffer.is_folded(node)));
+	}
+
+	#[test]
+	fn folding_updates_the_line_layout() {
+		let mut buffer = JsonBuffer::parse(r#"{"a": [1, 2], "b": 3}"#).unwrap();
+		let a = buffer.resolve("/a").unwrap();
+		assert_eq!(buffer.line_count(0), 7);
+		buffer.fold(a);
+		assert_eq!(buffer.line_count(0), 4);
+		assert_eq!(buffer.line_of(buffer.resolve("/b").unwrap()), 2);
+		buffer.unfold(a);
+		assert_eq!(buffer.line_count(0), 7);
+	}
 }
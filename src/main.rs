@@ -1,18 +1,38 @@
 use skulpin::CoordinateSystem;
 use skulpin::LogicalSize;
 use skulpin::skia_safe;
-use skia_safe::{Rect, Point, Canvas};
+use skia_safe::{Rect, Canvas, Color4f};
 
 use skulpin::app::AppBuilder;
+use skulpin::app::AppControl;
 use skulpin::app::AppUpdateArgs;
 use skulpin::app::AppDrawArgs;
 use skulpin::app::AppError;
 use skulpin::app::AppHandler;
 use skulpin::app::VirtualKeyCode;
 use skulpin::app::InputState;
+use skulpin::app::MouseScrollDelta;
+use std::collections::HashMap;
 use std::ffi::CString;
+use std::path::PathBuf;
+use std::rc::Rc;
 
-use skulpin_test::{JsonBuffer, JsonNode, JsonVariant, JsonBufferMode, JsonInput};
+use skulpin_test::{BufferLayout, ChangeKind, Completion, Difference, Highlight, JsonBuffer, LayoutMetrics, TokenKind, JsonNode, JsonNumber, JsonVariant, JsonBufferMode, JsonInput, MergeSide, PatchOperation, Schema};
+
+mod command_line;
+mod keymap;
+mod theme;
+
+use command_line::{CommandLine, ExCommand, LineEvent};
+use keymap::{Command, Keymap};
+use theme::Theme;
+
+const VIEW_WIDTH: f32 = 900.;
+const VIEW_HEIGHT: f32 = 600.;
+const WHEEL_LINES: f32 = 3.;
+const DEFAULT_THEME: &str = "themes/dark.toml";
+const COMPLETION_ROWS: usize = 8;
+const COMPLETION_CHARS: usize = 40;
 
 fn main() {
 	// Setup logging
@@ -25,7 +45,7 @@ fn main() {
 	// Set up the coordinate system to be fixed at 900x600, and use this as the default window size
 	// This means the drawing code can be written as though the window is always 900x600. The
 	// output will be automatically scaled so that it's always visible.
-	let logical_size = LogicalSize::new(900, 600);
+	let logical_size = LogicalSize::new(VIEW_WIDTH as u32, VIEW_HEIGHT as u32);
 	let visible_range = skulpin::skia_safe::Rect {
 		left: 0.0,
 		right: logical_size.width as f32,
@@ -43,65 +63,486 @@ fn main() {
 }
 
 struct ExampleApp {
-	pos: f32,
 	buffer: JsonBuffer,
+	// The buffer open before this one, which ":e#" switches back to.
+	alternate: Option<JsonBuffer>,
+	keymap: Keymap,
+	theme_path: PathBuf,
+	theme: Theme,
+	style: BufferStyle,
+	layout: BufferLayout,
+	command_line: CommandLine,
+	diff: Option<DiffView>,
+	// The schema given at startup, for documents that don't name their own.
+	schema: Option<Rc<Schema>>,
+	completion: Option<CompletionPopup>,
+	last_error: Option<String>,
+}
+
+// Suggestions from the schema for the primary selection in insert mode,
+// found again whenever the selection or the document changes.
+struct CompletionPopup {
+	node: usize,
+	revision: u64,
+	items: Vec<Completion>,
+	index: usize,
+}
+
+// Another document shown to the left of the buffer, with the differences
+// from it to the buffer highlighted in both.
+struct DiffView {
+	old: JsonBuffer,
+	id_field: Option<String>,
+	// The buffer revision the differences were found at.
+	revision: u64,
+	differences: Vec<Difference>,
+	layout: BufferLayout,
+}
+
+impl DiffView {
+	fn new(old: JsonBuffer, id_field: Option<String>, buffer: &JsonBuffer) -> DiffView {
+		let mut view = DiffView {
+			old,
+			id_field,
+			revision: buffer.revision(),
+			differences: Vec::new(),
+			layout: BufferLayout::default(),
+		};
+		view.old.selections = Vec::new();
+		view.compare(buffer);
+		view
+	}
+	fn compare(&mut self, buffer: &JsonBuffer) {
+		self.differences = self.old.diff(buffer, self.id_field.as_deref());
+		self.revision = buffer.revision();
+	}
+	// Scrolls the old document so that whatever matches the primary selection
+	// sits level with it.
+	fn align(&mut self, buffer: &JsonBuffer) {
+		let primary = match buffer.primary_selection() {
+			Some(primary) => primary,
+			None => return,
+		};
+		if let Some(node) = self.old.resolve(&buffer.pointer_of(primary)) {
+			let row = buffer.line_of(primary).saturating_sub(buffer.scroll);
+			self.old.scroll = self.old.line_of(node).saturating_sub(row);
+		}
+	}
+	fn summary(&self) -> String {
+		let count = |kind| self.differences.iter().filter(|d| d.kind == kind).count();
+		format!(
+			"+{} -{} ~{} >{}",
+			count(ChangeKind::Added),
+			count(ChangeKind::Removed),
+			count(ChangeKind::Changed),
+			count(ChangeKind::Moved),
+		)
+	}
 }
 
 impl ExampleApp {
 	pub fn new() -> Self {
-		let json = JsonBuffer {
-			nodes: vec![JsonNode {
-				variant: JsonVariant::Array(vec![1, 2, 3]),
-				parent: 0,
-				left: 0,
-				right: 0,
-			}, JsonNode {
-				variant: JsonVariant::Null,
-				parent: 0,
-				left: 0,
-				right: 2,
-			}, JsonNode {
-				variant: JsonVariant::Null,
-				parent: 0,
-				left: 1,
-				right: 3,
-			}, JsonNode {
-				variant: JsonVariant::Object(vec![4, 5]),
-				parent: 0,
-				left: 2,
-				right: 0,
-			}, JsonNode {
-				variant: JsonVariant::ObjectEntry("name".to_string(), 6),
-				parent: 3,
-				left: 3,
-				right: 5,
-			}, JsonNode {
-				variant: JsonVariant::ObjectEntry("age".to_string(), 7),
-				parent: 3,
-				left: 4,
-				right: 3,
-			}, JsonNode {
-				variant: JsonVariant::String("Charlie Stanton".to_string()),
-				parent: 4,
-				left: 4,
-				right: 4,
-			}, JsonNode {
-				variant: JsonVariant::Number(20.),
-				parent: 5,
-				left: 5,
-				right: 5,
-			}],
-			selections: vec![3],
-			mode: JsonBufferMode::Normal,
-		};
+		let mut json = JsonBuffer::new(vec![JsonNode {
+			variant: JsonVariant::Array(vec![1, 2, 3]),
+			parent: 0,
+			left: 0,
+			right: 0,
+		}, JsonNode {
+			variant: JsonVariant::Null,
+			parent: 0,
+			left: 0,
+			right: 2,
+		}, JsonNode {
+			variant: JsonVariant::Null,
+			parent: 0,
+			left: 1,
+			right: 3,
+		}, JsonNode {
+			variant: JsonVariant::Object(vec![4, 5]),
+			parent: 0,
+			left: 2,
+			right: 0,
+		}, JsonNode {
+			variant: JsonVariant::ObjectEntry("name".to_string(), 6),
+			parent: 3,
+			left: 3,
+			right: 5,
+		}, JsonNode {
+			variant: JsonVariant::ObjectEntry("age".to_string(), 7),
+			parent: 3,
+			left: 4,
+			right: 3,
+		}, JsonNode {
+			variant: JsonVariant::String("Charlie Stanton".to_string()),
+			parent: 4,
+			left: 4,
+			right: 4,
+		}, JsonNode {
+			variant: JsonVariant::Number(JsonNumber::from(20_i64)),
+			parent: 5,
+			left: 5,
+			right: 5,
+		}]);
+		json.selections = vec![3];
+
+		let theme_path = PathBuf::from(std::env::var("JSONED_THEME").unwrap_or_else(|_| DEFAULT_THEME.to_string()));
+		let mut last_error = None;
+		let theme = Theme::load(&theme_path).unwrap_or_else(|err| {
+			last_error = Some(err);
+			Theme::default()
+		});
+
+		let keymap_path = std::env::var("JSONED_KEYMAP").ok().map(PathBuf::from);
+		let keymap = Keymap::load(keymap_path.as_deref()).unwrap_or_else(|err| {
+			last_error = Some(err);
+			Keymap::default()
+		});
+
+		let schema_path = std::env::var("JSONED_SCHEMA").ok().map(PathBuf::from);
+		let schema = schema_path.and_then(|path| Schema::load(&path).map_err(|err| last_error = Some(err)).ok()).map(Rc::new);
+		json.set_schema(schema.clone());
 
 		ExampleApp {
-			pos: 0.0,
 			buffer: json,
+			alternate: None,
+			keymap,
+			theme_path,
+			style: BufferStyle::new(&theme),
+			theme,
+			layout: BufferLayout::default(),
+			command_line: CommandLine::new(),
+			diff: None,
+			schema,
+			completion: None,
+			last_error,
+		}
+	}
+	// Keeps the current theme if the file can't be read.
+	fn reload_theme(&mut self) {
+		match Theme::load(&self.theme_path) {
+			Ok(theme) => {
+				self.style = BufferStyle::new(&theme);
+				self.theme = theme;
+				self.layout = BufferLayout::default();
+			},
+			Err(err) => self.last_error = Some(err),
+		}
+	}
+	fn run_command(&mut self, command: Option<Command>, input_state: &InputState, app_control: &mut AppControl) {
+		match command {
+			Some(Command::Quit) => app_control.enqueue_terminate_process(),
+			Some(Command::ReloadTheme) => self.reload_theme(),
+			Some(Command::CmdlineMode) => {
+				self.command_line.open();
+				self.buffer.mode = JsonBufferMode::Command;
+				self.last_error = None;
+			},
+			Some(Command::NextCompletion) | Some(Command::PreviousCompletion) => {
+				if let Some(popup) = &mut self.completion {
+					let len = popup.items.len();
+					popup.index = if command == Some(Command::NextCompletion) {
+						(popup.index + 1) % len
+					} else {
+						(popup.index + len - 1) % len
+					};
+				}
+			},
+			Some(Command::AcceptCompletion) => {
+				if let Some(popup) = self.completion.take() {
+					let node = popup.node;
+					self.run_buffer_edit(|buffer| buffer.complete(node, &popup.items[popup.index]));
+				}
+			},
+			_ => self.buffer.update(input_state, command.as_ref(), &self.style),
+		}
+	}
+	fn refresh_completions(&mut self) {
+		let primary = match self.buffer.primary_selection() {
+			Some(primary) if self.buffer.mode == JsonBufferMode::Insert => primary,
+			_ => {
+				self.completion = None;
+				return;
+			},
+		};
+		let revision = self.buffer.revision();
+		if matches!(&self.completion, Some(popup) if popup.node == primary && popup.revision == revision) {
+			return;
+		}
+		let items = self.buffer.completions(primary);
+		self.completion = if items.is_empty() {
+			None
+		} else {
+			Some(CompletionPopup {
+				node: primary,
+				revision,
+				items,
+				index: 0,
+			})
+		};
+	}
+	fn execute(&mut self, text: &str, input_state: &InputState, app_control: &mut AppControl) -> Result<(), String> {
+		match ExCommand::parse(text)? {
+			ExCommand::Write(path) => self.write(path)?,
+			ExCommand::Quit { force } => {
+				let alternate_modified = self.alternate.as_ref().is_some_and(|alternate| alternate.modified);
+				if (self.buffer.modified || alternate_modified) && !force {
+					return Err("no write since last change (add ! to override)".to_string());
+				}
+				app_control.enqueue_terminate_process();
+			},
+			ExCommand::WriteQuit => {
+				self.write(None)?;
+				app_control.enqueue_terminate_process();
+			},
+			ExCommand::Edit { path, force } => {
+				if self.buffer.modified && !force {
+					return Err("no write since last change (add ! to override)".to_string());
+				}
+				let buffer = JsonBuffer::open(&path)?;
+				self.switch_to(buffer, force)?;
+				self.attach_schema()?;
+			},
+			ExCommand::Alternate => {
+				let alternate = self.alternate.take().ok_or("no alternate buffer")?;
+				self.switch_to(alternate, false)?;
+			},
+			ExCommand::InferSchema { force } => {
+				let mut schema = self.buffer.infer_schema();
+				schema.modified = true;
+				self.switch_to(schema, force)?;
+			},
+			ExCommand::Schema(path) => {
+				self.schema = match path {
+					Some(path) => Some(Rc::new(Schema::load(&path)?)),
+					None => None,
+				};
+				self.buffer.set_schema(self.schema.clone());
+			},
+			ExCommand::Diff { path, id_field } => {
+				let path = path.or_else(|| self.buffer.path.clone()).ok_or("no file to compare with")?;
+				let old = JsonBuffer::open(&path)?;
+				self.diff = Some(DiffView::new(old, id_field, &self.buffer));
+				self.layout.set_highlights(HashMap::new());
+			},
+			ExCommand::DiffOff => {
+				self.diff = None;
+				self.layout.set_highlights(HashMap::new());
+			},
+			ExCommand::Merge { base, theirs } => {
+				let base = JsonBuffer::open(&base)?;
+				let theirs = JsonBuffer::open(&theirs)?;
+				let mut merged = JsonBuffer::merge(&base, &self.buffer, &theirs);
+				merged.path = self.buffer.path.clone();
+				merged.modified = true;
+				self.buffer = merged;
+				self.layout = BufferLayout::default();
+				self.diff = None;
+				self.attach_schema()?;
+				self.run_buffer_edit(JsonBuffer::enter_conflict_mode);
+			},
+			ExCommand::Apply(path) => {
+				let text = std::fs::read_to_string(&path).map_err(|err| format!("{}: {}", path.display(), err))?;
+				let patch: serde_json::Value = serde_json::from_str(&text).map_err(|err| format!("{}: {}", path.display(), err))?;
+				match patch {
+					serde_json::Value::Array(operations) => {
+						let operations = operations.iter()
+							.map(PatchOperation::from_value)
+							.collect::<Result<Vec<_>, _>>()
+							.map_err(|err| format!("{}: {}", path.display(), err))?;
+						self.buffer.apply_patch(&operations)?;
+					},
+					patch => self.buffer.apply_merge_patch(&patch),
+				}
+			},
+			ExCommand::WritePatch(path) => {
+				let mut text = serde_json::to_string_pretty(&self.buffer.patch_value()).map_err(|err| err.to_string())?;
+				text.push('\n');
+				std::fs::write(&path, text).map_err(|err| format!("{}: {}", path.display(), err))?;
+			},
+			ExCommand::WriteRust(path) => {
+				let text = self.buffer.rust_types();
+				std::fs::write(&path, text).map_err(|err| format!("{}: {}", path.display(), err))?;
+			},
+			ExCommand::Select(query) => self.run_buffer_edit(|buffer| buffer.select_matching(&query)),
+			ExCommand::Goto(pointer) => {
+				self.buffer.select_pointer(&pointer)?;
+				self.buffer.scroll_to_primary(self.style.visible_lines());
+			},
+			ExCommand::Sort => self.run_buffer_edit(JsonBuffer::sort),
+			ExCommand::Run(command) => self.run_command(Some(command), input_state, app_control),
+		}
+		Ok(())
+	}
+	// Validates the buffer against the schema it names with `$schema`, found
+	// relative to its file, or else the one given at startup. Schemas named by
+	// URL can't be fetched, so those are left to the startup one too.
+	fn attach_schema(&mut self) -> Result<(), String> {
+		self.buffer.set_schema(self.schema.clone());
+		let declared = match self.buffer.declared_schema() {
+			Some(declared) if !declared.contains("://") => PathBuf::from(declared),
+			_ => return Ok(()),
+		};
+		let path = match self.buffer.path.as_ref().and_then(|path| path.parent()) {
+			Some(directory) => directory.join(declared),
+			None => declared,
+		};
+		self.buffer.set_schema(Some(Rc::new(Schema::load(&path)?)));
+		Ok(())
+	}
+	// Opens `buffer`, keeping the one it replaces as the alternate. That drops
+	// the old alternate, so unless `force` is set it mustn't have changes.
+	fn switch_to(&mut self, buffer: JsonBuffer, force: bool) -> Result<(), String> {
+		if self.alternate.as_ref().is_some_and(|alternate| alternate.modified) && !force {
+			return Err("no write since last change to the alternate buffer (add ! to override)".to_string());
+		}
+		self.alternate = Some(std::mem::replace(&mut self.buffer, buffer));
+		self.layout = BufferLayout::default();
+		self.diff = None;
+		self.completion = None;
+		Ok(())
+	}
+	// Writes to `path`, or to the buffer's own file if there isn't one.
+	fn write(&mut self, path: Option<PathBuf>) -> Result<(), String> {
+		let path = path.or_else(|| self.buffer.path.clone()).ok_or("no file name")?;
+		self.buffer.save(&path)
+	}
+	fn run_buffer_edit<F: FnOnce(&mut JsonBuffer)>(&mut self, f: F) {
+		f(&mut self.buffer);
+		self.buffer.scroll_to_primary(self.style.visible_lines());
+	}
+	// Lists the completions beside the primary selection, scrolled to keep
+	// the chosen one in view and kept inside the window.
+	fn draw_completions(&self, canvas: &mut Canvas) {
+		let popup = match &self.completion {
+			Some(popup) => popup,
+			None => return,
+		};
+		let anchor = match self.layout.selections.get(self.layout.primary) {
+			Some(Some(rect)) => *rect,
+			_ => return,
+		};
+		let style = &self.style;
+		let origin_x = if self.diff.is_some() {VIEW_WIDTH / 2.} else {0.};
+		let rows = popup.items.len().min(COMPLETION_ROWS);
+		let first = (popup.index + 1).saturating_sub(rows);
+		let labels: Vec<String> = popup.items[first..first + rows].iter()
+			.map(|item| item.label.chars().take(COMPLETION_CHARS).collect())
+			.collect();
+		let width = (labels.iter().map(|label| label.chars().count()).max().unwrap_or(0) + 2) as f32 * style.character_width;
+		let height = rows as f32 * style.line_height;
+		let left = (origin_x + anchor.right + style.character_width).min(VIEW_WIDTH - width).max(0.);
+		let top = (anchor.top - self.buffer.scroll as f32 * style.line_height)
+			.min(VIEW_HEIGHT - style.line_height - height)
+			.max(0.);
+
+		canvas.draw_rect(Rect::new(left, top, left + width, top + height), &style.status_background_paint);
+		for (row, label) in labels.iter().enumerate() {
+			let row_top = top + row as f32 * style.line_height;
+			if first + row == popup.index {
+				canvas.draw_rect(Rect::new(left, row_top, left + width, row_top + style.line_height), &style.primary_paint);
+			}
+			let baseline = row_top + style.line_height * 0.8;
+			canvas.draw_str(label, (left + style.character_width, baseline), &style.font, &style.status_text_paint);
+		}
+	}
+	fn draw_status_line(&self, canvas: &mut Canvas) {
+		let style = &self.style;
+		let buffer = &self.buffer;
+		let top = VIEW_HEIGHT - style.line_height;
+		let baseline = top + style.line_height * 0.8;
+		canvas.draw_rect(Rect::new(0., top, VIEW_WIDTH, VIEW_HEIGHT), &style.status_background_paint);
+
+		if buffer.mode == JsonBufferMode::Command {
+			let line = &self.command_line;
+			canvas.draw_str(format!(":{}", line.text), (0., baseline), &style.font, &style.status_text_paint);
+			let cursor_x = (line.cursor + 1) as f32 * style.character_width;
+			canvas.draw_rect(Rect::new(cursor_x, top, cursor_x + 1., VIEW_HEIGHT), &style.status_text_paint);
+			return;
+		}
+
+		let pointer = match buffer.primary_selection().map(|primary| buffer.pointer_of(primary)) {
+			Some(ref pointer) if pointer.is_empty() => "(root)".to_string(),
+			Some(pointer) => pointer,
+			None => "".to_string(),
+		};
+		let mut left = format!(" {}  {}", mode_name(buffer.mode), pointer);
+		if let Some(diff) = &self.diff {
+			left.push_str(&format!("  diff {}", diff.summary()));
+		}
+		let diagnostics = buffer.diagnostics();
+		if !diagnostics.is_empty() {
+			let shown = buffer.primary_selection()
+				.and_then(|primary| diagnostics.iter().find(|diagnostic| diagnostic.node == primary))
+				.unwrap_or(&diagnostics[0]);
+			let pointer = buffer.pointer_of(shown.node);
+			left.push_str(&format!(
+				"  {} {}: {} {}",
+				diagnostics.len(),
+				if diagnostics.len() == 1 {"problem"} else {"problems"},
+				if pointer.is_empty() {"(root)"} else {&pointer},
+				shown.message,
+			));
+		}
+		let conflicts = buffer.unresolved_conflicts().count();
+		if conflicts > 0 {
+			left.push_str(&format!("  {} conflicts", conflicts));
+		}
+		if buffer.mode == JsonBufferMode::Conflict {
+			if let Some(conflict) = buffer.primary_selection().and_then(|primary| buffer.conflict_at(primary)) {
+				let conflict = &buffer.conflicts[conflict];
+				left.push_str(&format!(
+					"  ours {}  theirs {}",
+					side_summary(conflict.side_value(MergeSide::Ours)),
+					side_summary(conflict.side_value(MergeSide::Theirs)),
+				));
+			}
+		}
+		canvas.draw_str(&left, (0., baseline), &style.font, &style.status_text_paint);
+
+		let file_name = buffer.path.as_ref()
+			.and_then(|path| path.file_name())
+			.map(|name| name.to_string_lossy().into_owned())
+			.unwrap_or_else(|| "[No Name]".to_string());
+		let right = format!(
+			"{} selected  {}{} ",
+			buffer.selections.len(),
+			file_name,
+			if buffer.modified {" [+]"} else {""},
+		);
+		let right_x = VIEW_WIDTH - right.chars().count() as f32 * style.character_width;
+		canvas.draw_str(&right, (right_x, baseline), &style.font, &style.status_text_paint);
+
+		if let Some(error) = &self.last_error {
+			let error_x = (left.chars().count() + 2) as f32 * style.character_width;
+			canvas.draw_str(error, (error_x, baseline), &style.font, &style.error_paint);
 		}
 	}
 }
 
+// One side of a conflict as compact JSON, cut short to fit the status line.
+fn side_summary(value: Option<serde_json::Value>) -> String {
+	const MAX_CHARS: usize = 24;
+	let text = match value {
+		Some(value) => value.to_string(),
+		None => "(removed)".to_string(),
+	};
+	if text.chars().count() > MAX_CHARS {
+		format!("{}…", text.chars().take(MAX_CHARS - 1).collect::<String>())
+	} else {
+		text
+	}
+}
+
+fn mode_name(mode: JsonBufferMode) -> &'static str {
+	match mode {
+		JsonBufferMode::Normal => "NORMAL",
+		JsonBufferMode::Insert => "INSERT",
+		JsonBufferMode::Visual => "VISUAL",
+		JsonBufferMode::Command => "COMMAND",
+		JsonBufferMode::Conflict => "CONFLICT",
+	}
+}
+
 impl AppHandler for ExampleApp {
 	fn update(
 		&mut self,
@@ -110,13 +551,23 @@ impl AppHandler for ExampleApp {
 		let input_state = update_args.input_state;
 		let app_control = update_args.app_control;
 
-		self.buffer.update(input_state);
-
-		//self.pos = ((update_args.time_state.update_count() as f32 / 30.0).sin() + 1.0) / 2.0;
-
-		if input_state.is_key_down(VirtualKeyCode::Q) {
-			app_control.enqueue_terminate_process();
+		if self.buffer.mode == JsonBufferMode::Command {
+			match self.command_line.update(input_state) {
+				Some(LineEvent::Submit(text)) => {
+					self.buffer.mode = JsonBufferMode::Normal;
+					if let Err(err) = self.execute(&text, input_state, app_control) {
+						self.last_error = Some(err);
+					}
+				},
+				Some(LineEvent::Cancel) => self.buffer.mode = JsonBufferMode::Normal,
+				None => {},
+			}
+			return;
 		}
+
+		let command = self.keymap.command_for(self.buffer.mode, input_state);
+		self.run_command(command, input_state, app_control);
+		self.refresh_completions();
 	}
 
 	fn draw(
@@ -126,29 +577,39 @@ impl AppHandler for ExampleApp {
 		let canvas = draw_args.canvas;
 
 		// Generally would want to clear data every time we draw
-		canvas.clear(skia_safe::Color::from_argb(0, 0, 0, 255));
+		canvas.clear(self.theme.background.to_color());
 
-		// Make a color to draw with
-		let mut paint = skia_safe::Paint::new(skia_safe::Color4f::new(1.0, 0.0, 0.0, 1.0), None);
-		paint.set_anti_alias(true);
-		paint.set_style(skia_safe::paint::Style::Stroke);
-		paint.set_stroke_width(2.0);
-
-		// Draw a line
-		canvas.draw_line(
-			skia_safe::Point::new(100.0, 500.0),
-			skia_safe::Point::new(800.0, 500.0),
-			&paint,
-		);
-
-		// Draw a circle
-		canvas.draw_circle(
-			skia_safe::Point::new(200.0 + (self.pos * 500.0), 420.0),
-			50.0,
-			&paint,
-		);
-
-		self.buffer.draw(canvas);
+		match &mut self.diff {
+			Some(diff) => {
+				if diff.revision != self.buffer.revision() {
+					diff.compare(&self.buffer);
+				}
+				diff.align(&self.buffer);
+				let old_highlights = diff.differences.iter()
+					.filter_map(|d| Some((d.old?, Highlight::Change(d.kind))))
+					.collect();
+				let new_highlights = diff.differences.iter()
+					.filter_map(|d| Some((d.new?, Highlight::Change(d.kind))))
+					.collect();
+				diff.layout.set_highlights(old_highlights);
+				self.layout.set_highlights(new_highlights);
+
+				let half = VIEW_WIDTH / 2.;
+				canvas.save();
+				canvas.clip_rect(Rect::new(0., 0., half, VIEW_HEIGHT), skia_safe::ClipOp::Intersect, false);
+				diff.old.draw(canvas, &self.style, &mut diff.layout);
+				canvas.restore();
+				canvas.save();
+				canvas.clip_rect(Rect::new(half, 0., VIEW_WIDTH, VIEW_HEIGHT), skia_safe::ClipOp::Intersect, false);
+				canvas.translate((half, 0.));
+				self.buffer.draw(canvas, &self.style, &mut self.layout);
+				canvas.restore();
+				canvas.draw_line((half, 0.), (half, VIEW_HEIGHT), &self.style.punctuation_paint);
+			},
+			None => self.buffer.draw(canvas, &self.style, &mut self.layout),
+		}
+		self.draw_completions(canvas);
+		self.draw_status_line(canvas);
 	}
 
 
@@ -160,96 +621,133 @@ impl AppHandler for ExampleApp {
 	}
 }
 
-fn rect_include_point(rect: &mut Rect, point: Point) {
-	if rect.left > point.x {
-		rect.left = point.x;
-	} else if rect.right < point.x {
-		rect.right = point.x;
-	}
-	if rect.top > point.y {
-		rect.top = point.y;
-	} else if rect.bottom < point.y {
-		rect.bottom = point.y;
+fn text_paint(color: &Color4f) -> skia_safe::Paint {
+	let mut paint = skia_safe::Paint::new(color, None);
+	paint.set_anti_alias(true);
+	paint.set_style(skia_safe::paint::Style::Fill);
+	paint
+}
+
+fn select_paint(color: &Color4f, width: f32, fill: bool) -> skia_safe::Paint {
+	let mut paint = skia_safe::Paint::new(color, None);
+	paint.set_anti_alias(true);
+	if fill {
+		paint.set_style(skia_safe::paint::Style::Fill);
+	} else {
+		paint.set_style(skia_safe::paint::Style::Stroke);
+		paint.set_stroke_width(width);
 	}
+	paint
 }
 
-struct TextBufferRenderer<'a> {
-	indent: f32,
-	line_num: f32,
-	line_so_far: String,
-	canvas: &'a mut Canvas,
+// Fonts and paints are built once from the theme, not on every frame.
+struct BufferStyle {
+	font: skia_safe::Font,
 	line_height: f32,
 	character_width: f32,
-	text_paint: &'a skia_safe::Paint,
-	font: &'a skia_safe::Font,
-	select_paint: &'a skia_safe::Paint,
-	selections: Vec<Option<Rect>>,
-	active_selections: Vec<usize>,
+	indent_width: usize,
+	key_paint: skia_safe::Paint,
+	string_paint: skia_safe::Paint,
+	number_paint: skia_safe::Paint,
+	bool_paint: skia_safe::Paint,
+	null_paint: skia_safe::Paint,
+	punctuation_paint: skia_safe::Paint,
+	select_paint: skia_safe::Paint,
+	primary_paint: skia_safe::Paint,
+	status_background_paint: skia_safe::Paint,
+	status_text_paint: skia_safe::Paint,
+	error_paint: skia_safe::Paint,
+	added_paint: skia_safe::Paint,
+	removed_paint: skia_safe::Paint,
+	changed_paint: skia_safe::Paint,
+	moved_paint: skia_safe::Paint,
+	conflict_paint: skia_safe::Paint,
+	diagnostic_paint: skia_safe::Paint,
 }
 
-impl<'a> TextBufferRenderer<'a> {
-	fn new<'b>(line_height: f32, character_width: f32, num_selections: usize, text_paint: &'b skia_safe::Paint, font: &'b skia_safe::Font, select_paint: &'b skia_safe::Paint, canvas: &'b mut Canvas) -> TextBufferRenderer<'b> {
-		TextBufferRenderer {
-			indent: 0.,
-			line_num: 0.,
-			line_so_far: "".to_string(),
-			canvas: canvas,
-			line_height: line_height,
-			character_width: character_width,
-			text_paint: text_paint,
-			font: font,
-			select_paint: select_paint,
-			selections: (0..num_selections).map(|_| Option::None).collect(),
-			active_selections: Vec::new(),
+impl BufferStyle {
+	fn new(theme: &Theme) -> BufferStyle {
+		let mut font = skia_safe::Font::default();
+		font.set_size(theme.font_size);
+
+		BufferStyle {
+			font,
+			line_height: theme.line_height,
+			character_width: theme.character_width,
+			indent_width: theme.indent_width,
+			key_paint: text_paint(&theme.key),
+			string_paint: text_paint(&theme.string),
+			number_paint: text_paint(&theme.number),
+			bool_paint: text_paint(&theme.boolean),
+			null_paint: text_paint(&theme.null),
+			punctuation_paint: text_paint(&theme.punctuation),
+			select_paint: select_paint(&theme.selection, theme.selection_width, theme.selection_fill),
+			// The primary selection stands out with a thicker outline.
+			primary_paint: select_paint(&theme.primary_selection, theme.selection_width * 2., theme.selection_fill),
+			status_background_paint: text_paint(&theme.status_background),
+			status_text_paint: text_paint(&theme.status_text),
+			error_paint: text_paint(&theme.error),
+			added_paint: text_paint(&theme.added),
+			removed_paint: text_paint(&theme.removed),
+			changed_paint: text_paint(&theme.changed),
+			moved_paint: text_paint(&theme.moved),
+			conflict_paint: text_paint(&theme.conflict),
+			diagnostic_paint: select_paint(&theme.error, 1.5, false),
 		}
 	}
-	fn add_to_selections(&mut self, point: Point) {
-		for index in &self.active_selections {
-			if let Some(Some(ref mut rect)) = self.selections.get_mut(*index) {
-				rect_include_point(rect, point);
-			} else {
-				self.selections[*index] = Option::Some(Rect::new(point.x, point.y, point.x, point.y));
-			}
+	// Lines of the buffer that fit above the status line.
+	fn visible_lines(&self) -> usize {
+		((VIEW_HEIGHT / self.line_height) as usize).saturating_sub(1)
+	}
+	fn metrics(&self) -> LayoutMetrics {
+		LayoutMetrics {
+			line_height: self.line_height,
+			character_width: self.character_width,
+			indent_width: self.indent_width,
+			visible_lines: self.visible_lines(),
 		}
 	}
-	fn add_to_line(&mut self, to_add: &str) {
-		let indent = self.indent*self.character_width*2.;
-		let left = indent + (self.line_so_far.len() as f32) * self.character_width;
-		self.line_so_far.push_str(to_add);
-		let right = indent + (self.line_so_far.len() as f32) * self.character_width;
-		let top = self.line_num*self.line_height;
-		let bottom = top + self.line_height;
-		self.add_to_selections(Point::new(left, top));
-		self.add_to_selections(Point::new(right, bottom));
-	}
-	fn start_selection(&mut self, index: usize) {
-		self.active_selections.push(index);
-	}
-	fn end_selection(&mut self, select: usize) {
-		let index = self.active_selections.iter().position(|&s| s==select).unwrap();
-		self.active_selections.remove(index);
-	}
-	fn draw_selections(&mut self) {
-		for maybe_rect in &self.selections {
-			if let Option::Some(rect) = maybe_rect {
-				self.canvas.draw_rect(
-					rect,
-					self.select_paint,
-				);
+	fn draw_layout(&self, canvas: &mut Canvas, layout: &BufferLayout) {
+		for (rect, highlight) in &layout.highlight_rects {
+			canvas.draw_rect(rect, self.highlight_paint(*highlight));
+		}
+		for (index, maybe_rect) in layout.selections.iter().enumerate() {
+			if let Some(rect) = maybe_rect {
+				let paint = if index == layout.primary {
+					&self.primary_paint
+				} else {
+					&self.select_paint
+				};
+				canvas.draw_rect(rect, paint);
 			}
 		}
+		for (pos, text, kind) in &layout.segments {
+			canvas.draw_str(text, *pos, &self.font, self.paint_for(*kind));
+		}
+		// Underlines the first line of each node with a diagnostic.
+		for rect in &layout.diagnostic_rects {
+			let y = rect.top + self.line_height - 1.;
+			canvas.draw_line((rect.left, y), (rect.right, y), &self.diagnostic_paint);
+		}
 	}
-	fn newline(&mut self) {
-		let pos = Point::new(self.indent*self.character_width*2., self.line_height*(self.line_num + 1.));
-		self.canvas.draw_str(self.line_so_far.as_str(), pos, self.font, self.text_paint);
-		self.line_so_far = "".to_string();
-		self.line_num += 1.;
-	}
-	fn indent(&mut self) {
-		self.indent += 1.;
+	fn highlight_paint(&self, highlight: Highlight) -> &skia_safe::Paint {
+		match highlight {
+			Highlight::Change(ChangeKind::Added) => &self.added_paint,
+			Highlight::Change(ChangeKind::Removed) => &self.removed_paint,
+			Highlight::Change(ChangeKind::Changed) => &self.changed_paint,
+			Highlight::Change(ChangeKind::Moved) => &self.moved_paint,
+			Highlight::Conflict => &self.conflict_paint,
+		}
 	}
-	fn unindent(&mut self) {
-		self.indent -= 1.;
+	fn paint_for(&self, kind: TokenKind) -> &skia_safe::Paint {
+		match kind {
+			TokenKind::Key => &self.key_paint,
+			TokenKind::String => &self.string_paint,
+			TokenKind::Number => &self.number_paint,
+			TokenKind::Bool => &self.bool_paint,
+			TokenKind::Null => &self.null_paint,
+			TokenKind::Punctuation => &self.punctuation_paint,
+		}
 	}
 }
 
@@ -316,167 +814,128 @@ fn input_from_state(input_state: &InputState) -> Option<JsonInput> {
 }
 
 trait Buffer {
-	fn draw(&self, draw_args: &mut Canvas);
-	fn update(&mut self, input_state: &InputState);
+	fn draw(&self, canvas: &mut Canvas, style: &BufferStyle, layout: &mut BufferLayout);
+	fn update(&mut self, input_state: &InputState, command: Option<&Command>, style: &BufferStyle);
+	fn run_command(&mut self, command: &Command, visible_lines: usize);
 }
 
 impl Buffer for JsonBuffer {
-	fn draw<'a>(&self, canvas: &'a mut Canvas) {
-		let mut font = skia_safe::Font::default();
-		font.set_size(18.0);
-
-		let mut text_paint = skia_safe::Paint::new(skia_safe::Color4f::new(1.0, 1.0, 1.0, 1.0), None);
-		text_paint.set_anti_alias(true);
-		text_paint.set_style(skia_safe::paint::Style::Fill);
+	fn draw(&self, canvas: &mut Canvas, style: &BufferStyle, layout: &mut BufferLayout) {
+		self.update_layout(&style.metrics(), layout);
 
-		let mut select_paint = skia_safe::Paint::new(skia_safe::Color4f::new(1.0, 0., 0., 1.), None);
-		select_paint.set_anti_alias(true);
-		select_paint.set_style(skia_safe::paint::Style::Stroke);
-		select_paint.set_stroke_width(1.);
-
-		let mut renderer = TextBufferRenderer::new(18., 9., self.selections.len(), &text_paint, &font, &select_paint, canvas);
-
-		let mut stack: Vec<(usize, bool, bool)> = vec![(0, false, false)];
-
-		while let Some((cur, visited, comma)) = stack.pop() {
-			let node = &self.nodes[cur];
-			let node_selection_index = self.selections.iter().position(|&n| n==cur);
-			if !visited {
-				if let Some(nsi) = node_selection_index {
-					renderer.start_selection(nsi);
-				}
-			}
-			match &node.variant {
-				JsonVariant::Null => {
-					if !visited {
-						renderer.add_to_line("null");
-						if comma {
-							renderer.add_to_line(",");
-						}
-						renderer.newline();
-						stack.push((cur, true, false));
-					}
-				},
-				JsonVariant::Bool(b) => {
-					if !visited {
-						renderer.add_to_line(b.to_string().as_str());
-						if comma {
-							renderer.add_to_line(",");
-						}renderer.newline();
-						stack.push((cur, true, false));
-					}
-				},
-				JsonVariant::Number(num) => {
-					if !visited {
-						renderer.add_to_line(num.to_string().as_str());
-						if comma {
-							renderer.add_to_line(",");
-						}
-						renderer.newline();
-						stack.push((cur, true, false));
-					}
-				},
-				JsonVariant::String(string) => {
-					if !visited {
-						renderer.add_to_line(format!("\"{}\"", string).as_str());
-						if comma {
-							renderer.add_to_line(",");
-						}
-						renderer.newline();
-						stack.push((cur, true, false));
-					}
-				},
-				JsonVariant::ObjectEntry(key, value) => {
-					if !visited {
-						renderer.add_to_line(format!("\"{}\": ", key).as_str());
-						stack.push((cur, true, false));
-						stack.push((*value, false, comma));
-					}
-				},
-				JsonVariant::Array(children) => {
-					if !visited {
-						renderer.add_to_line("[");
-						stack.push((cur, true, comma));
-						renderer.newline();
-						renderer.indent();
-						let mut child_has_comma = false;
-						for child in children.iter().rev() {
-							stack.push((*child, false, child_has_comma));
-							child_has_comma = true;
-						}
-					} else {
-						renderer.unindent();
-						renderer.add_to_line("]");
-						if comma {
-							renderer.add_to_line(",");
-						}
-						renderer.newline();
-					}
-				},
-				JsonVariant::Object(children) => {
-					if !visited {
-						renderer.add_to_line("{");
-						stack.push((cur, true, comma));
-						renderer.newline();
-						renderer.indent();
-						let mut child_has_comma = false;
-						for child in children.iter().rev() {
-							stack.push((*child, false, child_has_comma));
-							child_has_comma = true;
-						}
-					} else {
-						renderer.unindent();
-						renderer.add_to_line("}");
-						if comma {
-							renderer.add_to_line(",");
-						}
-						renderer.newline();
-					}
-				},
-			}
-			if visited {
-				if let Some(nsi) = node_selection_index {
-					renderer.end_selection(nsi);
-				}
+		canvas.save();
+		canvas.translate((0., -(self.scroll as f32) * style.line_height));
+		style.draw_layout(canvas, layout);
+		canvas.restore();
+	}
+	fn update(&mut self, input_state: &InputState, command: Option<&Command>, style: &BufferStyle) {
+		let visible_lines = style.visible_lines();
+		let before = self.primary_selection().map(|primary| (primary, self.line_of(primary)));
+		if let Some(command) = command {
+			self.run_command(command, visible_lines);
+		} else if self.mode == JsonBufferMode::Insert {
+			if let Some(input) = input_from_state(input_state) {
+				self.input(input);
 			}
 		}
-
-		renderer.draw_selections();
-	}
-	fn update(&mut self, input_state: &InputState) {
-		match self.mode {
-			JsonBufferMode::Normal => {
-				if input_state.is_key_just_down(VirtualKeyCode::K) {
-					self.select_up();
-				} else if input_state.is_key_just_down(VirtualKeyCode::J) {
-					self.select_down();
-				} else if input_state.is_key_just_down(VirtualKeyCode::H) {
-					self.select_parent();
-				} else if input_state.is_key_just_down(VirtualKeyCode::L) {
-					self.select_first_child();
-				} else if input_state.is_key_just_down(VirtualKeyCode::O) {
-					self.new_down_sibling();
-				} else if input_state.is_key_just_down(VirtualKeyCode::I) {
-					self.mode = JsonBufferMode::Insert;
-				} else if input_state.is_key_just_down(VirtualKeyCode::A) {
-					self.objectify();
-				} else if input_state.is_key_just_down(VirtualKeyCode::R) {
-					self.new_first_child();
-				} else if input_state.is_key_just_down(VirtualKeyCode::S) {
-					self.stringify();
-				} else if input_state.is_key_just_down(VirtualKeyCode::M) {
-					self.select_all_children();
-				}
-			},
-			JsonBufferMode::Insert => {
-				if input_state.is_key_just_down(VirtualKeyCode::Escape) {
-					self.mode = JsonBufferMode::Normal;
-				} else {
-					if let Some(input) = input_from_state(input_state) {
-						self.input(input);
-					}
-				}
-			},
+		let after = self.primary_selection().map(|primary| (primary, self.line_of(primary)));
+		if before != after {
+			self.scroll_to_primary(visible_lines);
 		}
+		let wheel_lines = match input_state.mouse_wheel_delta() {
+			MouseScrollDelta::LineDelta(_, y) => -y * WHEEL_LINES,
+			MouseScrollDelta::PixelDelta(position) => -position.y as f32 / style.line_height,
+		};
+		if wheel_lines != 0. {
+			self.scroll_by(wheel_lines.round() as isize);
+		}
+	}
+	fn run_command(&mut self, command: &Command, visible_lines: usize) {
+		let visual = self.mode == JsonBufferMode::Visual;
+		match command {
+			Command::SelectUp => self.select_up(),
+			Command::SelectDown => self.select_down(),
+			Command::SelectParent => self.select_parent(),
+			Command::SelectFirstChild => self.select_first_child(),
+			Command::SelectLastChild => self.select_last_child(),
+			Command::SelectAllChildren => self.select_all_children(),
+			Command::SelectDescendants => self.select_descendants(),
+			Command::SelectLeaves => self.select_leaves(),
+			Command::SelectAtDepth(depth) => self.select_at_depth(*depth),
+			Command::SelectNextNode => self.select_next_node(),
+			Command::SelectPreviousNode => self.select_previous_node(),
+			Command::SelectNextLeaf => self.select_next_leaf(),
+			Command::SelectPreviousLeaf => self.select_previous_leaf(),
+			Command::SelectDocumentStart => self.select_document_start(),
+			Command::SelectDocumentEnd => self.select_document_end(),
+			Command::NextPrimary => self.next_primary(),
+			Command::PreviousPrimary => self.previous_primary(),
+			Command::KeepPrimary => self.keep_primary_selection(),
+			Command::DropPrimary => self.drop_primary_selection(),
+			Command::NewDownSibling => self.new_down_sibling(),
+			Command::NewUpSibling => self.new_up_sibling(),
+			Command::NewFirstChild => self.new_first_child(),
+			Command::Objectify if visual => self.visual_objectify(),
+			Command::Objectify => self.objectify(),
+			Command::Stringify => self.stringify(),
+			Command::Delete => self.delete(),
+			Command::Yank => self.yank(),
+			Command::Put => self.put(),
+			Command::Wrap => self.wrap(),
+			Command::NormalMode if visual => self.exit_visual(),
+			Command::NormalMode => self.mode = JsonBufferMode::Normal,
+			Command::InsertMode => self.mode = JsonBufferMode::Insert,
+			Command::VisualMode => self.enter_visual(),
+			Command::ConflictMode => self.enter_conflict_mode(),
+			Command::NextConflict => self.next_conflict(),
+			Command::PreviousConflict => self.previous_conflict(),
+			Command::PickOurs => self.pick_side(MergeSide::Ours),
+			Command::PickTheirs => self.pick_side(MergeSide::Theirs),
+			Command::PickBase => self.pick_side(MergeSide::Base),
+			Command::ExtendUp => self.extend_up(),
+			Command::ExtendDown => self.extend_down(),
+			Command::ToggleFold => self.toggle_fold(),
+			Command::OpenAllFolds => self.open_all_folds(),
+			Command::CloseAllFolds => self.close_all_folds(),
+			Command::FoldToDepth(depth) => self.fold_to_depth(*depth),
+			Command::PageDown => self.scroll_by(visible_lines as isize),
+			Command::PageUp => self.scroll_by(-(visible_lines as isize)),
+			Command::Center => self.center_primary(visible_lines),
+			// Handled by the app before the buffer sees them.
+			Command::ReloadTheme | Command::Quit | Command::CmdlineMode => {},
+			Command::NextCompletion | Command::PreviousCompletion | Command::AcceptCompletion => {},
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn switching_keeps_a_changed_alternate_unless_forced() {
+		let mut app = ExampleApp::new();
+		let first = app.buffer.value_at(0);
+		app.buffer.delete();
+		app.switch_to(JsonBuffer::parse("1").unwrap(), false).unwrap();
+		assert_eq!(app.buffer.value_at(0), serde_json::json!(1));
+		assert!(app.alternate.as_ref().unwrap().modified);
+
+		assert!(app.switch_to(JsonBuffer::parse("2").unwrap(), false).is_err());
+		assert_eq!(app.buffer.value_at(0), serde_json::json!(1));
+
+		// Swapping back and forth loses neither buffer.
+		let alternate = app.alternate.take().unwrap();
+		app.switch_to(alternate, false).unwrap();
+		assert_ne!(app.buffer.value_at(0), first);
+		assert_eq!(app.alternate.as_ref().unwrap().value_at(0), serde_json::json!(1));
+
+		app.buffer.modified = false;
+		app.switch_to(JsonBuffer::parse("2").unwrap(), false).unwrap();
+		app.buffer.delete();
+		assert!(app.switch_to(JsonBuffer::parse("3").unwrap(), false).is_ok());
+		assert!(app.switch_to(JsonBuffer::parse("4").unwrap(), false).is_err());
+		app.switch_to(JsonBuffer::parse("4").unwrap(), true).unwrap();
+		assert_eq!(app.alternate.as_ref().unwrap().value_at(0), serde_json::json!(3));
 	}
 }
@@ -0,0 +1,316 @@
+use serde_json::Value;
+
+use crate::patch::values_equal;
+use crate::{JsonBuffer, JsonBufferMode, JsonNode, JsonVariant};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MergeSide {
+	Base,
+	Ours,
+	Theirs,
+}
+
+// A node the two sides changed in different ways. The merged document holds
+// our version, or theirs where we removed it, until a side is picked. Each
+// version is kept as its own arena rooted at index 0, like the register, and
+// is None where that side doesn't have the node.
+pub struct Conflict {
+	pub node: usize,
+	pub base: Option<Vec<JsonNode>>,
+	pub ours: Option<Vec<JsonNode>>,
+	pub theirs: Option<Vec<JsonNode>>,
+}
+
+impl Conflict {
+	pub fn side(&self, side: MergeSide) -> Option<&Vec<JsonNode>> {
+		match side {
+			MergeSide::Base => self.base.as_ref(),
+			MergeSide::Ours => self.ours.as_ref(),
+			MergeSide::Theirs => self.theirs.as_ref(),
+		}
+	}
+	pub fn side_value(&self, side: MergeSide) -> Option<Value> {
+		self.side(side).map(|nodes| JsonBuffer::new(nodes.clone()).value_at(0))
+	}
+}
+
+impl JsonBuffer {
+	// Merges the changes `ours` and `theirs` each made to `base`. Where only
+	// one side changed something, its change is taken, and values are the same
+	// if they're equal as JSON, so 1 and 1.0 don't conflict. Objects are merged
+	// member by member, and arrays item by item while all three have the same
+	// length. Anything else both sides changed becomes a conflict, including
+	// the whole of an array that either side added or removed items from.
+	pub fn merge(base: &JsonBuffer, ours: &JsonBuffer, theirs: &JsonBuffer) -> JsonBuffer {
+		let mut merger = Merger {
+			base,
+			ours,
+			theirs,
+			merged: JsonBuffer::new(vec![JsonNode {
+				variant: JsonVariant::Null,
+				parent: 0,
+				left: 0,
+				right: 0,
+			}]),
+		};
+		let root = merger.merge_values(Some(0), 0, 0);
+		let mut merged = merger.merged;
+		merged.replace_node(0, root);
+		for conflict in merged.conflicts.iter_mut().filter(|conflict| conflict.node == root) {
+			conflict.node = 0;
+		}
+		merged
+	}
+	// The conflicts still in the document. Editing a conflicting node by hand
+	// detaches it, which resolves it.
+	pub fn unresolved_conflicts(&self) -> impl Iterator<Item = (usize, &Conflict)> {
+		self.conflicts.iter()
+			.enumerate()
+			.filter(move |(_, conflict)| self.is_attached(conflict.node))
+	}
+	// The conflict that `node` is or is inside of.
+	pub fn conflict_at(&self, node: usize) -> Option<usize> {
+		let mut cur = node;
+		loop {
+			if let Some((i, _)) = self.unresolved_conflicts().find(|(_, conflict)| conflict.node == cur) {
+				return Some(i);
+			}
+			if self.nodes[cur].parent == cur {
+				return None;
+			}
+			cur = self.nodes[cur].parent;
+		}
+	}
+	pub fn enter_conflict_mode(&mut self) {
+		if self.unresolved_conflicts().next().is_some() {
+			self.mode = JsonBufferMode::Conflict;
+			if self.primary_selection().and_then(|primary| self.conflict_at(primary)).is_none() {
+				self.next_conflict();
+			}
+		}
+	}
+	// Selects the first conflict after the primary selection, wrapping around
+	// at the end of the document.
+	pub fn next_conflict(&mut self) {
+		let here = self.primary_selection().map(|primary| self.position_path(primary)).unwrap_or_default();
+		let mut conflicts: Vec<(Vec<usize>, usize)> = self.unresolved_conflicts()
+			.map(|(_, conflict)| (self.position_path(conflict.node), conflict.node))
+			.collect();
+		conflicts.sort();
+		let next = conflicts.iter().find(|(position, _)| *position > here).or_else(|| conflicts.first());
+		if let Some(&(_, node)) = next {
+			self.select_conflict(node);
+		}
+	}
+	pub fn previous_conflict(&mut self) {
+		let here = self.primary_selection().map(|primary| self.position_path(primary)).unwrap_or_default();
+		let mut conflicts: Vec<(Vec<usize>, usize)> = self.unresolved_conflicts()
+			.map(|(_, conflict)| (self.position_path(conflict.node), conflict.node))
+			.collect();
+		conflicts.sort();
+		let previous = conflicts.iter().rev().find(|(position, _)| *position < here).or_else(|| conflicts.last());
+		if let Some(&(_, node)) = previous {
+			self.select_conflict(node);
+		}
+	}
+	fn select_conflict(&mut self, node: usize) {
+		let pointer = self.pointer_of(node);
+		let _ = self.select_pointer(&pointer);
+	}
+	// Resolves the conflict holding the primary selection with one side's
+	// version, removing the node if that side doesn't have it, then moves on
+	// to the next conflict. Conflict mode ends with the last one.
+	pub fn pick_side(&mut self, side: MergeSide) {
+		let index = match self.primary_selection().and_then(|primary| self.conflict_at(primary)) {
+			Some(index) => index,
+			None => return,
+		};
+		self.edit();
+		let conflict = self.conflicts.remove(index);
+		match conflict.side(side) {
+			Some(subtree) => {
+				let new = self.graft(subtree);
+				self.replace_node(conflict.node, new);
+				let node = if conflict.node == 0 {0} else {new};
+				self.record_replace(node);
+				self.selections = vec![node];
+			},
+			None => {
+				let path = self.pointer_of(conflict.node);
+				self.record_remove(conflict.node);
+				let parent = self.nodes[self.nodes[conflict.node].parent].parent;
+				let _ = self.remove_at(&path);
+				self.selections = vec![parent];
+			},
+		}
+		self.primary = 0;
+		if self.unresolved_conflicts().next().is_some() {
+			self.next_conflict();
+		} else if self.mode == JsonBufferMode::Conflict {
+			self.mode = JsonBufferMode::Normal;
+		}
+	}
+}
+
+// Builds the merged document in its own arena from nodes of the three
+// inputs, recording conflicts on it as they're found.
+struct Merger<'a> {
+	base: &'a JsonBuffer,
+	ours: &'a JsonBuffer,
+	theirs: &'a JsonBuffer,
+	merged: JsonBuffer,
+}
+
+impl<'a> Merger<'a> {
+	fn merge_values(&mut self, base: Option<usize>, ours: usize, theirs: usize) -> usize {
+		let ours_value = self.ours.value_at(ours);
+		let theirs_value = self.theirs.value_at(theirs);
+		if values_equal(&ours_value, &theirs_value) {
+			return self.copy(self.ours, ours);
+		}
+		if let Some(base) = base {
+			let base_value = self.base.value_at(base);
+			if values_equal(&base_value, &ours_value) {
+				return self.copy(self.theirs, theirs);
+			}
+			if values_equal(&base_value, &theirs_value) {
+				return self.copy(self.ours, ours);
+			}
+		}
+		let (base_buffer, ours_buffer, theirs_buffer) = (self.base, self.ours, self.theirs);
+		let base_variant = base.map(|base| &base_buffer.nodes[base].variant);
+		match (base_variant, &ours_buffer.nodes[ours].variant, &theirs_buffer.nodes[theirs].variant) {
+			(None, JsonVariant::Object(_), JsonVariant::Object(_)) |
+			(Some(JsonVariant::Object(_)), JsonVariant::Object(_), JsonVariant::Object(_)) => {
+				self.merge_objects(base, ours, theirs)
+			},
+			(Some(JsonVariant::Array(base_items)), JsonVariant::Array(ours_items), JsonVariant::Array(theirs_items))
+				if base_items.len() == ours_items.len() && base_items.len() == theirs_items.len() => {
+				let items: Vec<usize> = (0..base_items.len())
+					.map(|i| self.merge_values(Some(base_items[i]), ours_items[i], theirs_items[i]))
+					.collect();
+				self.push_container(JsonVariant::Array(items))
+			},
+			// Without matching items up across insertions and removals there's
+			// no telling which of ours goes with which of theirs, so an array
+			// whose length changed conflicts as a whole.
+			_ => self.conflict(base, Some(ours), Some(theirs)),
+		}
+	}
+	// Members keep our order, followed by those only they added.
+	fn merge_objects(&mut self, base: Option<usize>, ours: usize, theirs: usize) -> usize {
+		let (base_buffer, ours_buffer, theirs_buffer) = (self.base, self.ours, self.theirs);
+		let mut entries = Vec::new();
+		for &entry in ours_buffer.children(ours) {
+			let key = entry_key(ours_buffer, entry);
+			let ours_value = ours_buffer.children(entry)[0];
+			let base_value = base.and_then(|base| member(base_buffer, base, key));
+			let value = match (member(theirs_buffer, theirs, key), base_value) {
+				(Some(theirs_value), base_value) => Some(self.merge_values(base_value, ours_value, theirs_value)),
+				// We added it.
+				(None, None) => Some(self.copy(ours_buffer, ours_value)),
+				// They removed it, and we left it alone.
+				(None, Some(base_value)) if values_equal(&base_buffer.value_at(base_value), &ours_buffer.value_at(ours_value)) => None,
+				(None, Some(base_value)) => Some(self.conflict(Some(base_value), Some(ours_value), None)),
+			};
+			if let Some(value) = value {
+				entries.push(self.push_entry(key, value));
+			}
+		}
+		for &entry in theirs_buffer.children(theirs) {
+			let key = entry_key(theirs_buffer, entry);
+			if member(ours_buffer, ours, key).is_some() {
+				continue;
+			}
+			let theirs_value = theirs_buffer.children(entry)[0];
+			let value = match base.and_then(|base| member(base_buffer, base, key)) {
+				// They added it.
+				None => Some(self.copy(theirs_buffer, theirs_value)),
+				// We removed it, and they left it alone.
+				Some(base_value) if values_equal(&base_buffer.value_at(base_value), &theirs_buffer.value_at(theirs_value)) => None,
+				Some(base_value) => Some(self.conflict(Some(base_value), None, Some(theirs_value))),
+			};
+			if let Some(value) = value {
+				entries.push(self.push_entry(key, value));
+			}
+		}
+		self.push_container(JsonVariant::Object(entries))
+	}
+	fn conflict(&mut self, base: Option<usize>, ours: Option<usize>, theirs: Option<usize>) -> usize {
+		let node = match (ours, theirs) {
+			(Some(ours), _) => self.copy(self.ours, ours),
+			(None, Some(theirs)) => self.copy(self.theirs, theirs),
+			(None, None) => self.merged.push_node(JsonVariant::Null),
+		};
+		self.merged.conflicts.push(Conflict {
+			node,
+			base: base.map(|base| self.base.copy_subtree(base)),
+			ours: ours.map(|ours| self.ours.copy_subtree(ours)),
+			theirs: theirs.map(|theirs| self.theirs.copy_subtree(theirs)),
+		});
+		node
+	}
+	fn copy(&mut self, from: &JsonBuffer, node: usize) -> usize {
+		self.merged.graft(&from.copy_subtree(node))
+	}
+	fn push_entry(&mut self, key: &str, value: usize) -> usize {
+		let entry = self.merged.push_node(JsonVariant::ObjectEntry(key.to_string(), value));
+		self.merged.link_children(entry);
+		entry
+	}
+	fn push_container(&mut self, variant: JsonVariant) -> usize {
+		let node = self.merged.push_node(variant);
+		self.merged.link_children(node);
+		node
+	}
+}
+
+fn entry_key(buffer: &JsonBuffer, entry: usize) -> &str {
+	match &buffer.nodes[entry].variant {
+		JsonVariant::ObjectEntry(key, _) => key,
+		_ => "",
+	}
+}
+
+// The value of the member of `object` named `key`.
+fn member(buffer: &JsonBuffer, object: usize, key: &str) -> Option<usize> {
+	buffer.children(object).iter()
+		.find(|&&entry| entry_key(buffer, entry) == key)
+		.map(|&entry| buffer.children(entry)[0])
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use serde_json::json;
+
+	fn merged(base: Value, ours: Value, theirs: Value) -> (Value, Vec<String>) {
+		let merged = JsonBuffer::merge(&JsonBuffer::from(&base), &JsonBuffer::from(&ours), &JsonBuffer::from(&theirs));
+		let conflicts = merged.unresolved_conflicts().map(|(_, conflict)| merged.pointer_of(conflict.node)).collect();
+		(merged.value_at(0), conflicts)
+	}
+
+	#[test]
+	fn numbers_are_compared_by_value() {
+		let (value, conflicts) = merged(json!({"a": 1, "b": 2}), json!({"a": 1.0, "b": 3}), json!({"a": 1, "b": 2.0}));
+		assert_eq!(value.to_string(), r#"{"a":1.0,"b":3}"#);
+		assert!(conflicts.is_empty());
+		let (value, conflicts) = merged(json!({"a": 1, "b": 2}), json!({"b": 2}), json!({"a": 1.0}));
+		assert_eq!(value, json!({}));
+		assert!(conflicts.is_empty());
+	}
+
+	#[test]
+	fn arrays_merge_item_by_item_only_at_the_same_length() {
+		let (value, conflicts) = merged(json!([1, 2, 3]), json!([9, 2, 3]), json!([1, 2, 8]));
+		assert_eq!(value, json!([9, 2, 8]));
+		assert!(conflicts.is_empty());
+		let (value, conflicts) = merged(json!({"a": [1, 2]}), json!({"a": [9, 2]}), json!({"a": [1, 2, 3]}));
+		assert_eq!(value, json!({"a": [9, 2]}));
+		assert_eq!(conflicts, vec!["/a"]);
+		// A change on one side alone is taken whatever its length.
+		let (value, conflicts) = merged(json!({"a": [1, 2]}), json!({"a": [1, 2]}), json!({"a": [1]}));
+		assert_eq!(value, json!({"a": [1]}));
+		assert!(conflicts.is_empty());
+	}
+}
@@ -0,0 +1,166 @@
+use std::cmp::Ordering;
+use std::fmt;
+
+// A number as it was written, so saving the document gives back the same
+// text, along with its value where an f64 can hold it.
+#[derive(Clone)]
+pub struct JsonNumber {
+	pub text: String,
+	pub value: Option<f64>,
+}
+
+impl JsonNumber {
+	// Reads `text` if it's a JSON number.
+	pub fn parse(text: &str) -> Option<JsonNumber> {
+		if !is_number(text) {
+			return None;
+		}
+		Some(JsonNumber {
+			text: text.to_string(),
+			value: text.parse::<f64>().ok().filter(|value| value.is_finite()),
+		})
+	}
+	// The shortest text that reads back as `value`, without a fractional part
+	// for whole numbers an f64 holds exactly. JSON has no text for NaN or the
+	// infinities.
+	pub fn from_f64(value: f64) -> Option<JsonNumber> {
+		let text = if value.fract() == 0. && value.abs() < 9_007_199_254_740_992. {
+			(value as i64).to_string()
+		} else {
+			serde_json::Number::from_f64(value)?.to_string()
+		};
+		Some(JsonNumber {
+			text,
+			value: Some(value),
+		})
+	}
+	// The exact value of a number written as a whole number.
+	pub fn as_integer(&self) -> Option<i128> {
+		self.text.parse().ok()
+	}
+	// Whether the number has no fractional part, however it's written.
+	pub fn is_integer(&self) -> bool {
+		self.as_integer().is_some() || self.value.is_some_and(|value| value.fract() == 0.)
+	}
+	// The value, if the shortest text that reads back as it is the same
+	// number as the text, so writing the f64 out loses nothing.
+	pub fn exact_f64(&self) -> Option<f64> {
+		let value = self.value?;
+		let decimal = decimal(&self.text)?;
+		(decimal == self::decimal(&format!("{:e}", value))?).then_some(value)
+	}
+}
+
+impl From<i64> for JsonNumber {
+	fn from(value: i64) -> JsonNumber {
+		JsonNumber {
+			text: value.to_string(),
+			value: Some(value as f64),
+		}
+	}
+}
+
+impl From<u64> for JsonNumber {
+	fn from(value: u64) -> JsonNumber {
+		JsonNumber {
+			text: value.to_string(),
+			value: Some(value as f64),
+		}
+	}
+}
+
+impl From<i128> for JsonNumber {
+	fn from(value: i128) -> JsonNumber {
+		JsonNumber {
+			text: value.to_string(),
+			value: Some(value as f64),
+		}
+	}
+}
+
+impl From<u128> for JsonNumber {
+	fn from(value: u128) -> JsonNumber {
+		JsonNumber {
+			text: value.to_string(),
+			value: Some(value as f64),
+		}
+	}
+}
+
+impl fmt::Display for JsonNumber {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.write_str(&self.text)
+	}
+}
+
+impl fmt::Debug for JsonNumber {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.write_str(&self.text)
+	}
+}
+
+// Numbers compare by value, so 1.0 equals 1, and whole numbers compare
+// exactly even past where an f64 can tell them apart.
+impl PartialEq for JsonNumber {
+	fn eq(&self, other: &JsonNumber) -> bool {
+		self.partial_cmp(other) == Some(Ordering::Equal)
+	}
+}
+
+impl PartialOrd for JsonNumber {
+	fn partial_cmp(&self, other: &JsonNumber) -> Option<Ordering> {
+		if self.text == other.text {
+			return Some(Ordering::Equal);
+		}
+		match (self.as_integer(), other.as_integer()) {
+			(Some(a), Some(b)) => Some(a.cmp(&b)),
+			_ => self.value?.partial_cmp(&other.value?),
+		}
+	}
+}
+
+// -?(0|[1-9][0-9]*)(\.[0-9]+)?([eE][+-]?[0-9]+)?
+fn is_number(text: &str) -> bool {
+	let digits = |s: &str| s.len() - s.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+	let rest = text.strip_prefix('-').unwrap_or(text);
+	let whole = digits(rest);
+	if whole == 0 || (whole > 1 && rest.starts_with('0')) {
+		return false;
+	}
+	let mut rest = &rest[whole..];
+	if let Some(fraction) = rest.strip_prefix('.') {
+		let len = digits(fraction);
+		if len == 0 {
+			return false;
+		}
+		rest = &fraction[len..];
+	}
+	if let Some(exponent) = rest.strip_prefix(['e', 'E']) {
+		let exponent = exponent.strip_prefix(['+', '-']).unwrap_or(exponent);
+		let len = digits(exponent);
+		if len == 0 {
+			return false;
+		}
+		rest = &exponent[len..];
+	}
+	rest.is_empty()
+}
+
+// The sign, significant digits and exponent of a number's text, so texts
+// that mean the same number give the same parts.
+fn decimal(text: &str) -> Option<(bool, String, i64)> {
+	let negative = text.starts_with('-');
+	let text = text.trim_start_matches('-');
+	let (mantissa, exponent) = text.split_once(['e', 'E']).unwrap_or((text, "0"));
+	let mut exponent: i64 = exponent.trim_start_matches('+').parse().ok()?;
+	let (whole, fraction) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+	exponent = exponent.checked_sub(fraction.len() as i64)?;
+	let digits = format!("{}{}", whole, fraction);
+	let digits = digits.trim_start_matches('0');
+	let significant = digits.trim_end_matches('0');
+	if significant.is_empty() {
+		return Some((negative, String::new(), 0));
+	}
+	exponent = exponent.checked_add((digits.len() - significant.len()) as i64)?;
+	Some((negative, significant.to_string(), exponent))
+}
@@ -0,0 +1,646 @@
+use serde_json::{Map, Value};
+
+use crate::pointer::{array_index, unescape_token};
+use crate::{JsonBuffer, JsonNode, JsonNumber, JsonVariant};
+
+// One RFC 6902 operation. Paths are JSON Pointers into the document as it
+// stands when the operation is applied, so a list of them is replayed in
+// order against the document they were recorded from.
+#[derive(Clone, Debug, PartialEq)]
+pub enum PatchOperation {
+	Add { path: String, value: Value },
+	Remove { path: String },
+	Replace { path: String, value: Value },
+	Move { from: String, path: String },
+	Copy { from: String, path: String },
+	Test { path: String, value: Value },
+}
+
+impl PatchOperation {
+	pub fn to_value(&self) -> Value {
+		let mut object = Map::new();
+		let (op, path) = match self {
+			PatchOperation::Add { path, .. } => ("add", path),
+			PatchOperation::Remove { path } => ("remove", path),
+			PatchOperation::Replace { path, .. } => ("replace", path),
+			PatchOperation::Move { path, .. } => ("move", path),
+			PatchOperation::Copy { path, .. } => ("copy", path),
+			PatchOperation::Test { path, .. } => ("test", path),
+		};
+		object.insert("op".to_string(), Value::String(op.to_string()));
+		match self {
+			PatchOperation::Move { from, .. } | PatchOperation::Copy { from, .. } => {
+				object.insert("from".to_string(), Value::String(from.clone()));
+			},
+			_ => {},
+		}
+		object.insert("path".to_string(), Value::String(path.clone()));
+		match self {
+			PatchOperation::Add { value, .. } | PatchOperation::Replace { value, .. } | PatchOperation::Test { value, .. } => {
+				object.insert("value".to_string(), value.clone());
+			},
+			_ => {},
+		}
+		Value::Object(object)
+	}
+	pub fn from_value(value: &Value) -> Result<PatchOperation, String> {
+		let object = value.as_object().ok_or("operation must be an object")?;
+		let string = |name: &str| -> Result<String, String> {
+			object.get(name)
+				.and_then(Value::as_str)
+				.map(str::to_string)
+				.ok_or_else(|| format!("operation needs a \"{}\" string", name))
+		};
+		let value = || object.get("value").cloned().ok_or("operation needs a \"value\"");
+		Ok(match string("op")?.as_str() {
+			"add" => PatchOperation::Add { path: string("path")?, value: value()? },
+			"remove" => PatchOperation::Remove { path: string("path")? },
+			"replace" => PatchOperation::Replace { path: string("path")?, value: value()? },
+			"move" => PatchOperation::Move { from: string("from")?, path: string("path")? },
+			"copy" => PatchOperation::Copy { from: string("from")?, path: string("path")? },
+			"test" => PatchOperation::Test { path: string("path")?, value: value()? },
+			op => return Err(format!("unknown operation \"{}\"", op)),
+		})
+	}
+	// The pointers the operation reads or writes.
+	fn paths(&self) -> Vec<&str> {
+		match self {
+			PatchOperation::Move { from, path } | PatchOperation::Copy { from, path } => vec![from, path],
+			PatchOperation::Add { path, .. }
+				| PatchOperation::Remove { path }
+				| PatchOperation::Replace { path, .. }
+				| PatchOperation::Test { path, .. } => vec![path],
+		}
+	}
+	// Whether applying this could change what `pointer` refers to. Adding,
+	// removing or moving a member shifts its siblings, so anything under the
+	// same container counts; a replace only affects its own subtree.
+	fn disturbs(&self, pointer: &str) -> bool {
+		let shifts_siblings = !matches!(self, PatchOperation::Replace { .. } | PatchOperation::Test { .. });
+		self.paths().into_iter().any(|path| {
+			let scope = if shifts_siblings {parent_pointer(path)} else {path};
+			contains_pointer(scope, pointer) || contains_pointer(pointer, path)
+		})
+	}
+}
+
+fn parent_pointer(pointer: &str) -> &str {
+	&pointer[..pointer.rfind('/').unwrap_or(0)]
+}
+
+// Whether `inner` is `outer` or somewhere beneath it.
+fn contains_pointer(outer: &str, inner: &str) -> bool {
+	inner == outer || (inner.starts_with(outer) && inner[outer.len()..].starts_with('/'))
+}
+
+impl JsonBuffer {
+	// The whole session's edits as a JSON Patch document.
+	pub fn patch_value(&self) -> Value {
+		Value::Array(self.patch.iter().map(PatchOperation::to_value).collect())
+	}
+	// Applies an RFC 6902 patch. If any operation fails, including a failed
+	// test, the document is left as it was.
+	pub fn apply_patch(&mut self, patch: &[PatchOperation]) -> Result<(), String> {
+		let saved = self.nodes.clone();
+		for (i, op) in patch.iter().enumerate() {
+			if let Err(err) = self.apply_operation(op) {
+				self.nodes = saved;
+				return Err(format!("operation {}: {}", i, err));
+			}
+		}
+		self.edit();
+		self.patch.extend(patch.iter().cloned());
+		self.drop_detached_selections();
+		Ok(())
+	}
+	// Applies an RFC 7396 merge patch, which can't fail.
+	pub fn apply_merge_patch(&mut self, patch: &Value) {
+		self.edit();
+		self.merge_into(0, patch);
+		self.drop_detached_selections();
+	}
+	fn apply_operation(&mut self, op: &PatchOperation) -> Result<(), String> {
+		match op {
+			PatchOperation::Add { path, value } => {
+				let node = self.push_value(value);
+				self.insert_at(path, node)
+			},
+			PatchOperation::Remove { path } => {
+				let target = self.resolve(path).ok_or_else(|| format!("nothing at \"{}\"", path))?;
+				self.invalidate(target);
+				self.remove_at(path).map(|_| ())
+			},
+			PatchOperation::Replace { path, value } => {
+				let target = self.resolve(path).ok_or_else(|| format!("nothing at \"{}\"", path))?;
+				let node = self.push_value(value);
+				self.replace_node(target, node);
+				self.invalidate(if target == 0 {0} else {node});
+				Ok(())
+			},
+			PatchOperation::Move { from, path } => {
+				if contains_pointer(from, path) && from != path {
+					return Err(format!("can't move \"{}\" into itself", from));
+				}
+				if let Some(source) = self.resolve(from) {
+					self.invalidate(source);
+				}
+				let node = self.remove_at(from)?;
+				self.insert_at(path, node)
+			},
+			PatchOperation::Copy { from, path } => {
+				let source = self.resolve(from).ok_or_else(|| format!("nothing at \"{}\"", from))?;
+				let value = self.value_at(source);
+				let node = self.push_value(&value);
+				self.insert_at(path, node)
+			},
+			PatchOperation::Test { path, value } => {
+				let target = self.resolve(path).ok_or_else(|| format!("nothing at \"{}\"", path))?;
+				if values_equal(&self.value_at(target), value) {
+					Ok(())
+				} else {
+					Err(format!("test failed at \"{}\"", path))
+				}
+			},
+		}
+	}
+	// Puts the detached value `node` at `path`: inserted into an array, or set
+	// as an object member, replacing any existing one.
+	fn insert_at(&mut self, path: &str, node: usize) -> Result<(), String> {
+		if path.is_empty() {
+			self.replace_node(0, node);
+			self.invalidate(0);
+			return Ok(());
+		}
+		let split = path.rfind('/').ok_or_else(|| format!("\"{}\" isn't a JSON Pointer", path))?;
+		let (parent_path, token) = (&path[..split], &path[split + 1..]);
+		let parent = self.resolve(parent_path).ok_or_else(|| format!("nothing at \"{}\"", parent_path))?;
+		match self.nodes[parent].variant {
+			JsonVariant::Array(ref mut children) => {
+				let position = if token == "-" {
+					children.len()
+				} else {
+					array_index(token).filter(|&i| i <= children.len()).ok_or_else(|| format!("bad array index in \"{}\"", path))?
+				};
+				children.insert(position, node);
+			},
+			JsonVariant::Object(_) => {
+				let key = unescape_token(token);
+				match self.resolve(path) {
+					Some(existing) => self.replace_node(existing, node),
+					None => {
+						let entry = self.push_node(JsonVariant::ObjectEntry(key, node));
+						self.link_children(entry);
+						if let Some(children) = self.children_mut(parent) {
+							children.push(entry);
+						}
+					},
+				}
+			},
+			_ => return Err(format!("\"{}\" isn't a container", parent_path)),
+		}
+		self.link_children(parent);
+		self.invalidate(node);
+		Ok(())
+	}
+	// Detaches the value at `path` and returns it. Removing an object member
+	// drops its entry along with it.
+	pub(crate) fn remove_at(&mut self, path: &str) -> Result<usize, String> {
+		let target = self.resolve(path).ok_or_else(|| format!("nothing at \"{}\"", path))?;
+		if target == 0 {
+			return Err("can't remove the root".to_string());
+		}
+		let mut member = target;
+		let mut parent = self.nodes[target].parent;
+		if let JsonVariant::ObjectEntry(_, _) = self.nodes[parent].variant {
+			member = parent;
+			parent = self.nodes[parent].parent;
+		}
+		if let Some(children) = self.children_mut(parent) {
+			children.retain(|&c| c!=member);
+		}
+		self.link_children(parent);
+		Ok(target)
+	}
+	// Puts the detached value `new` where `old` is. The root stays at index 0,
+	// so a new root's contents are moved there instead.
+	pub(crate) fn replace_node(&mut self, old: usize, new: usize) {
+		if old == 0 {
+			let variant = std::mem::replace(&mut self.nodes[new].variant, JsonVariant::Null);
+			self.nodes[0] = JsonNode {
+				variant,
+				parent: 0,
+				left: 0,
+				right: 0,
+			};
+			self.link_children(0);
+			return;
+		}
+		let parent = self.nodes[old].parent;
+		match self.nodes[parent].variant {
+			JsonVariant::ObjectEntry(_, ref mut child) => *child = new,
+			JsonVariant::Array(ref mut children) | JsonVariant::Object(ref mut children) => {
+				for child in children.iter_mut().filter(|c| **c==old) {
+					*child = new;
+				}
+			},
+			_ => {},
+		}
+		self.link_children(parent);
+	}
+	fn merge_into(&mut self, target: usize, patch: &Value) {
+		let members = match patch {
+			Value::Object(members) => members,
+			_ => {
+				let node = self.push_value(patch);
+				self.replace_node(target, node);
+				self.record_replace(if target == 0 {0} else {node});
+				return;
+			},
+		};
+		let target = if let JsonVariant::Object(_) = self.nodes[target].variant {
+			target
+		} else {
+			let node = self.push_node(JsonVariant::Object(Vec::new()));
+			self.replace_node(target, node);
+			let node = if target == 0 {0} else {node};
+			self.record_replace(node);
+			node
+		};
+		for (key, value) in members {
+			let existing = self.children(target).iter().copied().find(|&entry| {
+				matches!(&self.nodes[entry].variant, JsonVariant::ObjectEntry(k, _) if k==key)
+			});
+			match (existing, value) {
+				(Some(entry), Value::Null) => {
+					self.record_remove(entry);
+					if let Some(children) = self.children_mut(target) {
+						children.retain(|&c| c!=entry);
+					}
+					self.link_children(target);
+				},
+				(None, Value::Null) => {},
+				(Some(entry), value) => {
+					let child = self.children(entry)[0];
+					self.merge_into(child, value);
+				},
+				(None, value) => {
+					let node = self.push_value(&without_nulls(value));
+					let entry = self.push_node(JsonVariant::ObjectEntry(key.clone(), node));
+					self.link_children(entry);
+					if let Some(children) = self.children_mut(target) {
+						children.push(entry);
+					}
+					self.link_children(target);
+					self.record_added(vec![entry]);
+				},
+			}
+		}
+	}
+	// Whether a node is still part of the document rather than left behind in
+	// the arena by a removal.
+	pub(crate) fn is_attached(&self, node: usize) -> bool {
+		let mut cur = node;
+		while cur != 0 {
+			let parent = self.nodes[cur].parent;
+			if parent == cur || !self.children(parent).contains(&cur) {
+				return false;
+			}
+			cur = parent;
+		}
+		true
+	}
+	fn drop_detached_selections(&mut self) {
+		let primary = self.primary_selection();
+		let selections: Vec<usize> = self.selections.iter().copied().filter(|&s| self.is_attached(s)).collect();
+		self.selections = if selections.is_empty() {vec![0]} else {selections};
+		self.restore_primary(primary);
+		let detached: Vec<usize> = self.folded.iter().copied().filter(|&f| !self.is_attached(f)).collect();
+		for node in detached {
+			self.folded.remove(&node);
+		}
+	}
+	// Typing produces an operation per key press, so a replace or rename is
+	// folded into the one it continues, unless something recorded since then
+	// could have changed what its path refers to.
+	// The nodes it changed should be invalidated separately, since a path
+	// can't tell apart members with the same key.
+	pub(crate) fn record(&mut self, op: PatchOperation) {
+		for i in (0..self.patch.len()).rev() {
+			match (&mut self.patch[i], &op) {
+				(PatchOperation::Replace { path, value }, PatchOperation::Replace { path: new_path, value: new_value }) if path == new_path => {
+					*value = new_value.clone();
+					return;
+				},
+				(PatchOperation::Move { from, path }, PatchOperation::Move { from: new_from, path: new_path }) if path == new_from => {
+					if from == new_path {
+						self.patch.remove(i);
+					} else {
+						*path = new_path.clone();
+					}
+					return;
+				},
+				_ => {},
+			}
+			if op.paths().into_iter().any(|path| self.patch[i].disturbs(path)) {
+				break;
+			}
+		}
+		self.patch.push(op);
+	}
+	pub(crate) fn record_replace(&mut self, node: usize) {
+		self.invalidate(node);
+		let op = PatchOperation::Replace {
+			path: self.pointer_of(node),
+			value: self.value_at(node),
+		};
+		self.record(op);
+	}
+	// Records the insertion of new subtrees, in document order so that each
+	// one's pointer is right once those before it have been added.
+	pub(crate) fn record_added(&mut self, mut nodes: Vec<usize>) {
+		nodes.sort_by_cached_key(|&node| self.position_path(node));
+		for node in nodes {
+			self.invalidate(node);
+			let op = PatchOperation::Add {
+				path: self.pointer_of(node),
+				value: self.value_at(node),
+			};
+			self.record(op);
+		}
+	}
+	// Records the removal of a node, which must still be in the tree.
+	pub(crate) fn record_remove(&mut self, node: usize) {
+		self.invalidate(node);
+		let op = PatchOperation::Remove {
+			path: self.pointer_of(node),
+		};
+		self.record(op);
+	}
+	// Records the moves that put the items of `array` into the order given by
+	// `reordered`, before the change is made to the array itself. Each item is
+	// moved forward into place from somewhere after everything already placed.
+	pub(crate) fn record_reorder(&mut self, array: usize, reordered: &[usize]) {
+		self.invalidate(array);
+		let base = self.pointer_of(array);
+		let mut current = self.children(array).to_vec();
+		for (i, &node) in reordered.iter().enumerate() {
+			let j = current.iter().position(|&c| c==node).unwrap_or(i);
+			if j != i {
+				current.remove(j);
+				current.insert(i, node);
+				self.record(PatchOperation::Move {
+					from: format!("{}/{}", base, j),
+					path: format!("{}/{}", base, i),
+				});
+			}
+		}
+	}
+	// Records that `entry`, which was at `from`, has had its key changed.
+	pub(crate) fn record_rename(&mut self, entry: usize, from: String) {
+		self.invalidate(entry);
+		let path = self.pointer_of(entry);
+		self.record(PatchOperation::Move { from, path });
+	}
+	// The index of each ancestor among its siblings, from the root down, which
+	// sorts nodes into document order.
+	pub(crate) fn position_path(&self, node: usize) -> Vec<usize> {
+		let mut positions = Vec::new();
+		let mut cur = node;
+		while self.nodes[cur].parent != cur {
+			let parent = self.nodes[cur].parent;
+			positions.push(self.children(parent).iter().position(|&c| c==cur).unwrap_or(0));
+			cur = parent;
+		}
+		positions.reverse();
+		positions
+	}
+}
+
+// Merge patches delete members with null, so nulls in a new member mean
+// nothing and are left out.
+fn without_nulls(value: &Value) -> Value {
+	match value {
+		Value::Object(members) => Value::Object(members.iter()
+			.filter(|(_, value)| !value.is_null())
+			.map(|(key, value)| (key.clone(), without_nulls(value)))
+			.collect()),
+		value => value.clone(),
+	}
+}
+
+// Equality for `test`, under which 1 and 1.0 are the same number and whole
+// numbers are compared exactly.
+pub(crate) fn values_equal(a: &Value, b: &Value) -> bool {
+	match (a, b) {
+		(Value::Number(a), Value::Number(b)) => JsonNumber::parse(&a.to_string()) == JsonNumber::parse(&b.to_string()),
+		(Value::Array(a), Value::Array(b)) => a.len() == b.len() && a.iter().zip(b).all(|(a, b)| values_equal(a, b)),
+		(Value::Object(a), Value::Object(b)) => {
+			a.len() == b.len() && a.iter().all(|(key, a)| matches!(b.get(key), Some(b) if values_equal(a, b)))
+		},
+		(a, b) => a == b,
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::JsonInput;
+	use serde_json::json;
+
+	// Applies the patch `buffer` recorded to the document it was parsed from
+	// and checks that gives the same document as the buffer now holds.
+	fn assert_replays(original: &str, buffer: &JsonBuffer) {
+		let mut replayed = JsonBuffer::parse(original).unwrap();
+		replayed.apply_patch(&operations(buffer.patch_value())).unwrap();
+		assert_eq!(replayed.value_at(0), buffer.value_at(0), "patch: {}", buffer.patch_value());
+	}
+
+	#[test]
+	fn new_members_replay_without_replacing_each_other() {
+		let original = r#"{"": 1, "a": [2, 3], "b": [4, 5]}"#;
+		let mut buffer = JsonBuffer::parse(original).unwrap();
+		buffer.selections = vec![0];
+		buffer.new_first_child();
+		buffer.new_down_sibling();
+		buffer.new_up_sibling();
+		buffer.selections = vec![buffer.resolve("/a").unwrap()];
+		buffer.objectify();
+		assert_eq!(buffer.value_at(0), json!({"": 1, "1": null, "2": null, "3": null, "a": {"": 2, "1": 3}, "b": [4, 5]}));
+		assert_eq!(buffer.children(0).len(), 6);
+		assert_replays(original, &buffer);
+
+		// Wrapping members in an object, and items in an object of their own.
+		buffer.selections = vec![buffer.children(0)[1]];
+		buffer.enter_visual();
+		buffer.extend_down();
+		buffer.wrap();
+		buffer.selections = vec![buffer.resolve("/b/0").unwrap()];
+		buffer.enter_visual();
+		buffer.extend_down();
+		buffer.visual_objectify();
+		assert_eq!(buffer.value_at(0), json!({"": 1, "1": null, "2": {"2": null, "3": null}, "a": {"": 2, "1": 3}, "b": [{"": 4, "1": 5}]}));
+		assert_replays(original, &buffer);
+	}
+
+	#[test]
+	fn sort_replays() {
+		let original = r#"{"b": [3, "x", 1, null, 2], "a": true}"#;
+		let mut buffer = JsonBuffer::parse(original).unwrap();
+		buffer.sort();
+		buffer.selections = vec![buffer.resolve("/b").unwrap()];
+		buffer.sort();
+		assert_eq!(buffer.value_at(0), json!({"a": true, "b": [null, 1, 2, 3, "x"]}));
+		assert_replays(original, &buffer);
+	}
+
+	#[test]
+	fn key_rename_replays() {
+		let original = r#"{"ab": 1, "c": {"ab": 2}}"#;
+		let mut buffer = JsonBuffer::parse(original).unwrap();
+		buffer.selections = vec![buffer.children(0)[0]];
+		buffer.input(JsonInput::Backspace);
+		buffer.input(JsonInput::Char('x'));
+		buffer.input(JsonInput::Char('y'));
+		assert_eq!(buffer.value_at(0), json!({"axy": 1, "c": {"ab": 2}}));
+		assert_replays(original, &buffer);
+	}
+
+	#[test]
+	fn delete_replays() {
+		let original = r#"{"a": [1, 2, 3, 4], "b": {"c": null}, "d": "e"}"#;
+		let mut buffer = JsonBuffer::parse(original).unwrap();
+		let a = buffer.resolve("/a").unwrap();
+		buffer.selections = vec![buffer.children(a)[1], buffer.children(a)[3], buffer.resolve("/b/c").unwrap(), buffer.resolve("/d").unwrap()];
+		buffer.delete();
+		assert_eq!(buffer.value_at(0), json!({"a": [1, 3], "b": {}}));
+		assert_replays(original, &buffer);
+
+		let mut buffer = JsonBuffer::parse(original).unwrap();
+		buffer.delete();
+		assert_eq!(buffer.value_at(0), Value::Null);
+		assert_replays(original, &buffer);
+	}
+
+	#[test]
+	fn root_wrap_replays() {
+		for original in [r#"{"a": 1}"#, "[1, 2]", "3"] {
+			let mut buffer = JsonBuffer::parse(original).unwrap();
+			buffer.wrap();
+			assert_eq!(buffer.value_at(0), json!([serde_json::from_str::<Value>(original).unwrap()]));
+			assert_replays(original, &buffer);
+
+			let mut buffer = JsonBuffer::parse(original).unwrap();
+			buffer.visual_objectify();
+			assert_replays(original, &buffer);
+		}
+	}
+
+	fn operations(patch: Value) -> Vec<PatchOperation> {
+		match patch {
+			Value::Array(ops) => ops.iter().map(|op| PatchOperation::from_value(op).unwrap()).collect(),
+			_ => unreachable!(),
+		}
+	}
+
+	// Applies `patch` to `document`, giving the result or the document as it
+	// was left after a failure.
+	fn patched(document: Value, patch: Value) -> Result<Value, Value> {
+		let mut buffer = JsonBuffer::from(&document);
+		match buffer.apply_patch(&operations(patch)) {
+			Ok(()) => Ok(buffer.value_at(0)),
+			Err(_) => Err(buffer.value_at(0)),
+		}
+	}
+
+	#[test]
+	fn dash_index_appends() {
+		assert_eq!(patched(json!({"foo": ["bar"]}), json!([{"op": "add", "path": "/foo/-", "value": ["abc", "def"]}])), Ok(json!({"foo": ["bar", ["abc", "def"]]})));
+		assert_eq!(patched(json!([1]), json!([{"op": "move", "from": "/0", "path": "/-"}])), Ok(json!([1])));
+		assert_eq!(patched(json!([1, 2]), json!([{"op": "copy", "from": "/0", "path": "/-"}])), Ok(json!([1, 2, 1])));
+		for op in ["remove", "replace", "test"] {
+			assert!(patched(json!([1]), json!([{"op": op, "path": "/-", "value": 1}])).is_err(), "{}", op);
+		}
+		assert!(patched(json!({"foo": ["bar"]}), json!([{"op": "add", "path": "/foo/2", "value": 1}])).is_err());
+		assert!(patched(json!({"foo": ["bar"]}), json!([{"op": "add", "path": "/foo/01", "value": 1}])).is_err());
+	}
+
+	#[test]
+	fn move_into_itself_fails() {
+		let document = json!({"a": {"b": {}}});
+		assert_eq!(patched(document.clone(), json!([{"op": "move", "from": "/a", "path": "/a/b/c"}])), Err(document.clone()));
+		assert_eq!(patched(document.clone(), json!([{"op": "move", "from": "/a", "path": "/a"}])), Ok(document.clone()));
+		// Only a whole token counts as a prefix.
+		assert_eq!(patched(json!({"a": 1, "ab": {}}), json!([{"op": "move", "from": "/a", "path": "/ab/c"}])), Ok(json!({"ab": {"c": 1}})));
+	}
+
+	#[test]
+	fn failed_test_rolls_back() {
+		let document = json!({"baz": "qux", "foo": ["a", 2, "c"]});
+		let patch = json!([
+			{"op": "replace", "path": "/baz", "value": "boo"},
+			{"op": "add", "path": "/foo/-", "value": 3},
+			{"op": "test", "path": "/baz", "value": "qux"},
+		]);
+		let mut buffer = JsonBuffer::from(&document);
+		assert!(buffer.apply_patch(&operations(patch)).is_err());
+		assert_eq!(buffer.value_at(0), document);
+		assert_eq!(buffer.patch_value(), json!([]));
+
+		// Numbers are equal by value, but nothing else is coerced.
+		assert!(patched(json!({"n": 1}), json!([{"op": "test", "path": "/n", "value": 1.0}])).is_ok());
+		assert!(patched(json!({"n": 1}), json!([{"op": "test", "path": "/n", "value": "1"}])).is_err());
+		assert!(patched(json!({"a": [1, 2]}), json!([{"op": "test", "path": "/a", "value": [2, 1]}])).is_err());
+		assert!(patched(json!({"a": {"x": 1, "y": 2}}), json!([{"op": "test", "path": "/a", "value": {"y": 2, "x": 1}}])).is_ok());
+	}
+
+	// The examples from RFC 7396's appendix.
+	#[test]
+	fn merge_patch_examples() {
+		let examples = [
+			(json!({"a": "b"}), json!({"a": "c"}), json!({"a": "c"})),
+			(json!({"a": "b"}), json!({"b": "c"}), json!({"a": "b", "b": "c"})),
+			(json!({"a": "b"}), json!({"a": null}), json!({})),
+			(json!({"a": "b", "b": "c"}), json!({"a": null}), json!({"b": "c"})),
+			(json!({"a": ["b"]}), json!({"a": "c"}), json!({"a": "c"})),
+			(json!({"a": "c"}), json!({"a": ["b"]}), json!({"a": ["b"]})),
+			(json!({"a": {"b": "c"}}), json!({"a": {"b": "d", "c": null}}), json!({"a": {"b": "d"}})),
+			(json!({"a": [{"b": "c"}]}), json!({"a": [1]}), json!({"a": [1]})),
+			(json!(["a", "b"]), json!(["c", "d"]), json!(["c", "d"])),
+			(json!({"a": "b"}), json!(["c"]), json!(["c"])),
+			(json!({"a": "foo"}), json!(null), json!(null)),
+			(json!({"a": "foo"}), json!("bar"), json!("bar")),
+			(json!({"e": null}), json!({"a": 1}), json!({"e": null, "a": 1})),
+			(json!([1, 2]), json!({"a": "b", "c": null}), json!({"a": "b"})),
+			(json!({}), json!({"a": {"bb": {"ccc": null}}}), json!({"a": {"bb": {}}})),
+		];
+		for (document, patch, expected) in examples {
+			let mut buffer = JsonBuffer::from(&document);
+			buffer.apply_merge_patch(&patch);
+			assert_eq!(buffer.value_at(0), expected, "{} merged with {}", document, patch);
+			let mut replayed = JsonBuffer::from(&document);
+			replayed.apply_patch(&operations(buffer.patch_value())).unwrap();
+			assert_eq!(replayed.value_at(0), expected, "replaying {}", buffer.patch_value());
+		}
+	}
+
+	#[test]
+	fn numbers_keep_their_text_through_patches() {
+		let original = r#"{"id": 18446744073709551617, "ratio": 1.0, "zero": -0}"#;
+		let mut buffer = JsonBuffer::parse(original).unwrap();
+		let patch = operations(json!([
+			{"op": "copy", "from": "/id", "path": "/copy"},
+			{"op": "test", "path": "/copy", "value": 18446744073709551617_u128},
+			{"op": "add", "path": "/big", "value": 123456789012345678901234567890_u128},
+		]));
+		buffer.apply_patch(&patch).unwrap();
+		buffer.apply_merge_patch(&serde_json::from_str(r#"{"ratio": 2.50, "zero": {"sub": -0}}"#).unwrap());
+		assert_eq!(buffer.value_at(0).to_string(), r#"{"big":123456789012345678901234567890,"copy":18446744073709551617,"id":18446744073709551617,"ratio":2.50,"zero":{"sub":-0}}"#);
+		assert!(buffer.patch_value().to_string().contains("18446744073709551617"));
+		assert_replays(original, &buffer);
+
+		// Whole numbers compare exactly, however large.
+		assert!(!values_equal(&json!(18446744073709551617_u128), &json!(18446744073709551616_u128)));
+		assert!(values_equal(&json!(18446744073709551616_u128), &serde_json::from_str("18446744073709551616.0").unwrap()));
+		assert!(values_equal(&json!(1000), &serde_json::from_str("1e3").unwrap()));
+	}
+}
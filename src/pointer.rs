@@ -0,0 +1,144 @@
+use crate::{JsonBuffer, JsonVariant};
+
+// Escapes a key for use as an RFC 6901 reference token.
+pub fn escape_token(key: &str) -> String {
+	key.replace('~', "~0").replace('/', "~1")
+}
+
+// Reverses `escape_token`. "~1" is replaced first so "~01" becomes "~1".
+pub fn unescape_token(token: &str) -> String {
+	token.replace("~1", "/").replace("~0", "~")
+}
+
+// An array index token: plain decimal with no leading zeros. "-" (one past
+// the end) is left to callers that can append.
+pub fn array_index(token: &str) -> Option<usize> {
+	let valid = !token.is_empty()
+		&& token.bytes().all(|b| b.is_ascii_digit())
+		&& (token == "0" || !token.starts_with('0'));
+	if valid {
+		token.parse().ok()
+	} else {
+		None
+	}
+}
+
+impl JsonBuffer {
+	// The JSON Pointer of a node, found by walking `parent` links up to the
+	// root. An entry has the same pointer as its value.
+	pub fn pointer_of(&self, node: usize) -> String {
+		let mut tokens = Vec::new();
+		let mut cur = node;
+		while self.nodes[cur].parent != cur {
+			let parent = self.nodes[cur].parent;
+			match &self.nodes[parent].variant {
+				JsonVariant::Array(children) => {
+					let index = children.iter().position(|&c| c==cur).unwrap_or(0);
+					tokens.push(index.to_string());
+				},
+				JsonVariant::Object(_) => {
+					if let JsonVariant::ObjectEntry(key, _) = &self.nodes[cur].variant {
+						tokens.push(escape_token(key));
+					}
+				},
+				_ => {},
+			}
+			cur = parent;
+		}
+		tokens.iter().rev().map(|token| format!("/{}", token)).collect()
+	}
+	// The node a JSON Pointer refers to, or None if it's malformed or names
+	// something that doesn't exist. Object members resolve to the value
+	// rather than the entry holding it.
+	pub fn resolve(&self, pointer: &str) -> Option<usize> {
+		if pointer.is_empty() {
+			return Some(0);
+		}
+		if !pointer.starts_with('/') {
+			return None;
+		}
+		let mut cur = 0;
+		for token in pointer[1..].split('/') {
+			cur = match &self.nodes[cur].variant {
+				JsonVariant::Array(children) => *children.get(array_index(token)?)?,
+				JsonVariant::Object(children) => {
+					let key = unescape_token(token);
+					children.iter().find_map(|&child| match &self.nodes[child].variant {
+						JsonVariant::ObjectEntry(k, value) if *k == key => Some(*value),
+						_ => None,
+					})?
+				},
+				_ => return None,
+			};
+		}
+		Some(cur)
+	}
+	// Selects the node at `pointer`, opening any folds hiding it.
+	pub fn select_pointer(&mut self, pointer: &str) -> Result<(), String> {
+		let node = self.resolve(pointer).ok_or_else(|| format!("no node at \"{}\"", pointer))?;
+		let mut cur = node;
+		while self.nodes[cur].parent != cur {
+			cur = self.nodes[cur].parent;
+			if self.folded.remove(&cur) {
+				self.touch();
+			}
+		}
+		self.selections = vec![node];
+		self.primary = 0;
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn tokens_round_trip() {
+		for key in ["a/b", "m~n", "~1", "~0/", "/~", ""] {
+			assert_eq!(unescape_token(&escape_token(key)), key);
+		}
+		assert_eq!(escape_token("a/b~c"), "a~1b~0c");
+		assert_eq!(unescape_token("~01"), "~1");
+	}
+
+	#[test]
+	fn resolves_rfc_6901_examples() {
+		let buffer = JsonBuffer::parse(r#"{"foo": ["bar", "baz"], "": 0, "a/b": 1, "m~n": 8, " ": 7}"#).unwrap();
+		let value = |pointer: &str| buffer.resolve(pointer).map(|node| buffer.value_at(node));
+		assert_eq!(value(""), Some(buffer.value_at(0)));
+		assert_eq!(value("/foo/0"), Some(serde_json::json!("bar")));
+		assert_eq!(value("/"), Some(serde_json::json!(0)));
+		assert_eq!(value("/a~1b"), Some(serde_json::json!(1)));
+		assert_eq!(value("/m~0n"), Some(serde_json::json!(8)));
+		assert_eq!(value("/ "), Some(serde_json::json!(7)));
+		assert_eq!(value("foo"), None);
+		assert_eq!(value("/missing"), None);
+	}
+
+	#[test]
+	fn array_indices_are_strict() {
+		let buffer = JsonBuffer::parse(r#"{"a": [10, 11]}"#).unwrap();
+		assert!(buffer.resolve("/a/1").is_some());
+		assert_eq!(buffer.resolve("/a/01"), None);
+		assert_eq!(buffer.resolve("/a/-"), None);
+		assert_eq!(buffer.resolve("/a/2"), None);
+		assert_eq!(buffer.resolve("/a/+1"), None);
+		assert_eq!(array_index("0"), Some(0));
+		assert_eq!(array_index("00"), None);
+		assert_eq!(array_index("-"), None);
+	}
+
+	#[test]
+	fn pointer_of_inverts_resolve() {
+		let buffer = JsonBuffer::parse(r#"{"a/b": [{"m~n": null}], "": [true]}"#).unwrap();
+		for node in 0..buffer.nodes.len() {
+			let pointer = buffer.pointer_of(node);
+			let value = match &buffer.nodes[node].variant {
+				JsonVariant::ObjectEntry(_, value) => *value,
+				_ => node,
+			};
+			assert_eq!(buffer.resolve(&pointer), Some(value), "{}", pointer);
+		}
+	}
+}
@@ -0,0 +1,634 @@
+use std::cell::{Ref, RefCell};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+use std::rc::Rc;
+
+use regex::Regex;
+use serde_json::Value;
+
+use crate::patch::values_equal;
+use crate::pointer::escape_token;
+use crate::{JsonBuffer, JsonVariant};
+
+// A JSON Schema, covering the validation keywords of draft 2020-12 other than
+// `format`, dynamic references and the `unevaluated*` ones. References may
+// only point within the same schema.
+pub struct Schema {
+	root: Value,
+	// Compiled `pattern` and `patternProperties` regexes, or None for those
+	// that don't compile and so are ignored.
+	patterns: RefCell<HashMap<String, Option<Regex>>>,
+}
+
+impl Schema {
+	pub fn parse(text: &str) -> Result<Schema, String> {
+		let root: Value = serde_json::from_str(text).map_err(|err| err.to_string())?;
+		match root {
+			Value::Object(_) | Value::Bool(_) => Ok(Schema {
+				root,
+				patterns: RefCell::new(HashMap::new()),
+			}),
+			_ => Err("a schema must be an object or a boolean".to_string()),
+		}
+	}
+	pub fn load(path: &Path) -> Result<Schema, String> {
+		let text = fs::read_to_string(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+		Schema::parse(&text).map_err(|err| format!("{}: {}", path.display(), err))
+	}
+	fn matches(&self, pattern: &str, text: &str) -> bool {
+		self.patterns.borrow_mut()
+			.entry(pattern.to_string())
+			.or_insert_with(|| Regex::new(pattern).ok())
+			.as_ref()
+			.is_none_or(|regex| regex.is_match(text))
+	}
+}
+
+// One step down from a node to a child, as a schema sees it.
+pub(crate) enum Step<'a> {
+	Key(&'a str),
+	Index(usize),
+}
+
+impl Schema {
+	pub(crate) fn at(&self, location: &str) -> Option<&Value> {
+		self.root.pointer(location)
+	}
+	// The locations that apply alongside `location`: itself, and whatever it
+	// refers to or combines, followed as deep as they go.
+	pub(crate) fn expand(&self, location: &str) -> Vec<String> {
+		let mut locations = Vec::new();
+		let mut pending = vec![location.to_string()];
+		while let Some(location) = pending.pop() {
+			if locations.contains(&location) {
+				continue;
+			}
+			let keywords = match self.at(&location) {
+				Some(Value::Object(keywords)) => keywords,
+				_ => continue,
+			};
+			if let Some(pointer) = keywords.get("$ref").and_then(Value::as_str).and_then(|reference| reference.strip_prefix('#')) {
+				pending.push(pointer.to_string());
+			}
+			for keyword in &["allOf", "anyOf", "oneOf"] {
+				if let Some(Value::Array(schemas)) = keywords.get(*keyword) {
+					pending.extend((0..schemas.len()).rev().map(|i| format!("{}/{}/{}", location, keyword, i)));
+				}
+			}
+			for keyword in &["then", "else"] {
+				if keywords.contains_key(*keyword) {
+					pending.push(format!("{}/{}", location, keyword));
+				}
+			}
+			locations.push(location);
+		}
+		locations
+	}
+	// The locations that apply to a child of something at `location`.
+	pub(crate) fn child(&self, location: &str, step: &Step) -> Vec<String> {
+		let mut children = Vec::new();
+		for location in self.expand(location) {
+			let keywords = match self.at(&location) {
+				Some(Value::Object(keywords)) => keywords,
+				_ => continue,
+			};
+			let at = |keyword: &str| format!("{}/{}", location, keyword);
+			match step {
+				Step::Key(key) => {
+					let mut evaluated = false;
+					if keywords.get("properties").and_then(Value::as_object).is_some_and(|properties| properties.contains_key(*key)) {
+						evaluated = true;
+						children.push(format!("{}/{}", at("properties"), escape_token(key)));
+					}
+					for pattern in keywords.get("patternProperties").and_then(Value::as_object).into_iter().flat_map(|patterns| patterns.keys()) {
+						if self.matches(pattern, key) {
+							evaluated = true;
+							children.push(format!("{}/{}", at("patternProperties"), escape_token(pattern)));
+						}
+					}
+					if !evaluated && keywords.contains_key("additionalProperties") {
+						children.push(at("additionalProperties"));
+					}
+				},
+				Step::Index(index) => {
+					match keywords.get("prefixItems") {
+						Some(Value::Array(schemas)) if *index < schemas.len() => children.push(format!("{}/{}", at("prefixItems"), index)),
+						_ if keywords.contains_key("items") => children.push(at("items")),
+						_ => {},
+					}
+				},
+			}
+		}
+		children
+	}
+}
+
+// A way the document breaks its schema. Problems with a member's key, such as
+// it not being allowed, are reported on the entry, and the rest on values.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diagnostic {
+	pub node: usize,
+	pub message: String,
+}
+
+// What's known about the document's validity. Results are kept for each
+// node and the schema location it was checked against, and only those for
+// nodes an edit touched, and their ancestors, are thrown away.
+#[derive(Default)]
+pub(crate) struct Validation {
+	schema: Option<Rc<Schema>>,
+	revision: Option<u64>,
+	results: HashMap<usize, HashMap<String, Vec<Diagnostic>>>,
+	dirty: HashSet<usize>,
+	diagnostics: Vec<Diagnostic>,
+}
+
+impl JsonBuffer {
+	pub fn set_schema(&mut self, schema: Option<Rc<Schema>>) {
+		*self.validation.get_mut() = Validation {
+			schema,
+			..Validation::default()
+		};
+		self.touch();
+	}
+	pub fn schema(&self) -> Option<Rc<Schema>> {
+		self.validation.borrow().schema.clone()
+	}
+	// The `$schema` the document names for itself, if any.
+	pub fn declared_schema(&self) -> Option<&str> {
+		let entry = self.children(0).iter().copied().find(|&entry| {
+			matches!(&self.nodes[entry].variant, JsonVariant::ObjectEntry(key, _) if key=="$schema")
+		})?;
+		match &self.nodes[self.children(entry)[0]].variant {
+			JsonVariant::String(schema) => Some(schema),
+			_ => None,
+		}
+	}
+	// The schema locations that apply to `node`, found by walking up to the
+	// root and then stepping back down through the schema. An entry gets
+	// those of its value.
+	pub(crate) fn schema_locations(&self, schema: &Schema, node: usize) -> Vec<String> {
+		let mut path = Vec::new();
+		let mut cur = node;
+		while self.nodes[cur].parent != cur {
+			let parent = self.nodes[cur].parent;
+			match &self.nodes[parent].variant {
+				JsonVariant::Array(children) => path.push(Step::Index(children.iter().position(|&c| c==cur).unwrap_or(0))),
+				JsonVariant::Object(_) => {
+					if let JsonVariant::ObjectEntry(key, _) = &self.nodes[cur].variant {
+						path.push(Step::Key(key));
+					}
+				},
+				_ => {},
+			}
+			cur = parent;
+		}
+		let mut locations = schema.expand("");
+		for step in path.iter().rev() {
+			locations = locations.iter().flat_map(|location| schema.child(location, step)).collect();
+			locations.dedup();
+		}
+		locations.iter().flat_map(|location| schema.expand(location)).collect()
+	}
+	// Called with each node an edit changes, added or is about to remove,
+	// while it's still in the tree. It and its ancestors as they are now are
+	// marked for checking again, since the node may be moved before then.
+	pub(crate) fn invalidate(&mut self, node: usize) {
+		let validation = self.validation.get_mut();
+		if validation.schema.is_none() {
+			return;
+		}
+		let mut cur = node;
+		validation.dirty.insert(cur);
+		while self.nodes[cur].parent != cur {
+			cur = self.nodes[cur].parent;
+			validation.dirty.insert(cur);
+		}
+	}
+	// Every violation of the schema, in document order, checking again only
+	// what's changed since the last call.
+	pub fn diagnostics(&self) -> Ref<'_, [Diagnostic]> {
+		let stale = self.validation.borrow().revision != Some(self.revision);
+		if stale {
+			let mut validation = self.validation.borrow_mut();
+			let validation = &mut *validation;
+			for node in validation.dirty.drain() {
+				validation.results.remove(&node);
+			}
+			validation.diagnostics = match validation.schema.clone() {
+				Some(schema) => {
+					let mut validator = Validator {
+						buffer: self,
+						schema: &schema,
+						results: &mut validation.results,
+					};
+					let mut diagnostics = validator.validate(0, "");
+					diagnostics.sort_by_cached_key(|diagnostic| self.position_path(diagnostic.node));
+					diagnostics.dedup();
+					diagnostics
+				},
+				None => Vec::new(),
+			};
+			validation.revision = Some(self.revision);
+		}
+		Ref::map(self.validation.borrow(), |validation| &validation.diagnostics[..])
+	}
+}
+
+struct Validator<'a> {
+	buffer: &'a JsonBuffer,
+	schema: &'a Schema,
+	results: &'a mut HashMap<usize, HashMap<String, Vec<Diagnostic>>>,
+}
+
+impl<'a> Validator<'a> {
+	// Checks `node` against the schema at `location`, a JSON Pointer into the
+	// schema document.
+	fn validate(&mut self, node: usize, location: &str) -> Vec<Diagnostic> {
+		if let Some(diagnostics) = self.results.get(&node).and_then(|results| results.get(location)) {
+			return diagnostics.clone();
+		}
+		// Stands in while this check runs, so a reference cycle ends here.
+		self.results.entry(node).or_default().insert(location.to_string(), Vec::new());
+		let schema = self.schema;
+		let diagnostics = match schema.root.pointer(location) {
+			Some(schema) => self.check(node, schema, location),
+			None => vec![self.diagnostic(node, format!("schema has nothing at \"{}\"", location))],
+		};
+		self.results.entry(node).or_default().insert(location.to_string(), diagnostics.clone());
+		diagnostics
+	}
+	fn is_valid(&mut self, node: usize, location: &str) -> bool {
+		self.validate(node, location).is_empty()
+	}
+	fn diagnostic(&self, node: usize, message: String) -> Diagnostic {
+		Diagnostic {
+			node,
+			message,
+		}
+	}
+	fn check(&mut self, node: usize, schema: &Value, location: &str) -> Vec<Diagnostic> {
+		let keywords = match schema {
+			Value::Bool(true) => return Vec::new(),
+			Value::Bool(false) => return vec![self.diagnostic(node, "not allowed here".to_string())],
+			Value::Object(keywords) => keywords,
+			_ => return Vec::new(),
+		};
+		let buffer = self.buffer;
+		let at = |keyword: &str| format!("{}/{}", location, escape_token(keyword));
+		let mut diagnostics = Vec::new();
+
+		if let Some(Value::String(reference)) = keywords.get("$ref") {
+			match reference.strip_prefix('#') {
+				Some(pointer) => diagnostics.extend(self.validate(node, pointer)),
+				None => diagnostics.push(self.diagnostic(node, format!("can't follow $ref \"{}\"", reference))),
+			}
+		}
+
+		let variant = &buffer.nodes[node].variant;
+		if let Some(types) = keywords.get("type") {
+			let allowed: Vec<&str> = match types {
+				Value::String(name) => vec![name],
+				Value::Array(names) => names.iter().filter_map(Value::as_str).collect(),
+				_ => Vec::new(),
+			};
+			if !allowed.is_empty() && !allowed.iter().any(|name| has_type(variant, name)) {
+				diagnostics.push(self.diagnostic(node, format!("expected {}, found {}", allowed.join(" or "), type_name(variant))));
+			}
+		}
+		if let Some(value) = keywords.get("const") {
+			if !values_equal(&buffer.value_at(node), value) {
+				diagnostics.push(self.diagnostic(node, format!("must be {}", value)));
+			}
+		}
+		if let Some(Value::Array(values)) = keywords.get("enum") {
+			let value = buffer.value_at(node);
+			if !values.iter().any(|allowed| values_equal(&value, allowed)) {
+				let allowed: Vec<String> = values.iter().map(Value::to_string).collect();
+				diagnostics.push(self.diagnostic(node, format!("must be one of {}", allowed.join(", "))));
+			}
+		}
+
+		match variant {
+			JsonVariant::Number(n) => {
+				if let Some(n) = n.value {
+					self.check_number(node, n, keywords, &mut diagnostics);
+				}
+			},
+			JsonVariant::String(s) => {
+				let length = s.chars().count() as f64;
+				if let Some(min) = keywords.get("minLength").and_then(Value::as_f64) {
+					if length < min {
+						diagnostics.push(self.diagnostic(node, format!("must be at least {} characters long", min)));
+					}
+				}
+				if let Some(max) = keywords.get("maxLength").and_then(Value::as_f64) {
+					if length > max {
+						diagnostics.push(self.diagnostic(node, format!("must be at most {} characters long", max)));
+					}
+				}
+				if let Some(Value::String(pattern)) = keywords.get("pattern") {
+					if !self.schema.matches(pattern, s) {
+						diagnostics.push(self.diagnostic(node, format!("must match /{}/", pattern)));
+					}
+				}
+			},
+			JsonVariant::Array(items) => self.check_array(node, items, keywords, location, &mut diagnostics),
+			JsonVariant::Object(entries) => self.check_object(node, entries, keywords, location, &mut diagnostics),
+			_ => {},
+		}
+
+		if let Some(Value::Array(schemas)) = keywords.get("allOf") {
+			for i in 0..schemas.len() {
+				diagnostics.extend(self.validate(node, &format!("{}/{}", at("allOf"), i)));
+			}
+		}
+		if let Some(Value::Array(schemas)) = keywords.get("anyOf") {
+			if !(0..schemas.len()).any(|i| self.is_valid(node, &format!("{}/{}", at("anyOf"), i))) {
+				diagnostics.push(self.diagnostic(node, "doesn't match any of the allowed schemas".to_string()));
+			}
+		}
+		if let Some(Value::Array(schemas)) = keywords.get("oneOf") {
+			let matched = (0..schemas.len()).filter(|i| self.is_valid(node, &format!("{}/{}", at("oneOf"), i))).count();
+			if matched == 0 {
+				diagnostics.push(self.diagnostic(node, "doesn't match any of the allowed schemas".to_string()));
+			} else if matched > 1 {
+				diagnostics.push(self.diagnostic(node, format!("matches {} schemas where only one is allowed", matched)));
+			}
+		}
+		if keywords.contains_key("not") && self.is_valid(node, &at("not")) {
+			diagnostics.push(self.diagnostic(node, "matches a schema it mustn't".to_string()));
+		}
+		if keywords.contains_key("if") {
+			let branch = if self.is_valid(node, &at("if")) {"then"} else {"else"};
+			if keywords.contains_key(branch) {
+				diagnostics.extend(self.validate(node, &at(branch)));
+			}
+		}
+		diagnostics
+	}
+	fn check_number(&self, node: usize, n: f64, keywords: &serde_json::Map<String, Value>, diagnostics: &mut Vec<Diagnostic>) {
+		let limit = |keyword: &str| keywords.get(keyword).and_then(Value::as_f64);
+		if let Some(min) = limit("minimum") {
+			if n < min {
+				diagnostics.push(self.diagnostic(node, format!("must be at least {}", min)));
+			}
+		}
+		if let Some(max) = limit("maximum") {
+			if n > max {
+				diagnostics.push(self.diagnostic(node, format!("must be at most {}", max)));
+			}
+		}
+		if let Some(min) = limit("exclusiveMinimum") {
+			if n <= min {
+				diagnostics.push(self.diagnostic(node, format!("must be greater than {}", min)));
+			}
+		}
+		if let Some(max) = limit("exclusiveMaximum") {
+			if n >= max {
+				diagnostics.push(self.diagnostic(node, format!("must be less than {}", max)));
+			}
+		}
+		if let Some(divisor) = limit("multipleOf").filter(|&divisor| divisor > 0.) {
+			let quotient = n / divisor;
+			if (quotient - quotient.round()).abs() > 1e-9 {
+				diagnostics.push(self.diagnostic(node, format!("must be a multiple of {}", divisor)));
+			}
+		}
+	}
+	fn check_array(&mut self, node: usize, items: &[usize], keywords: &serde_json::Map<String, Value>, location: &str, diagnostics: &mut Vec<Diagnostic>) {
+		let at = |keyword: &str| format!("{}/{}", location, escape_token(keyword));
+		let count = items.len() as f64;
+		if let Some(min) = keywords.get("minItems").and_then(Value::as_f64) {
+			if count < min {
+				diagnostics.push(self.diagnostic(node, format!("must have at least {} items", min)));
+			}
+		}
+		if let Some(max) = keywords.get("maxItems").and_then(Value::as_f64) {
+			if count > max {
+				diagnostics.push(self.diagnostic(node, format!("must have at most {} items", max)));
+			}
+		}
+		if keywords.get("uniqueItems") == Some(&Value::Bool(true)) {
+			let values: Vec<Value> = items.iter().map(|&item| self.buffer.value_at(item)).collect();
+			for (i, &item) in items.iter().enumerate() {
+				if values[..i].iter().any(|earlier| values_equal(earlier, &values[i])) {
+					diagnostics.push(self.diagnostic(item, "repeats an earlier item".to_string()));
+				}
+			}
+		}
+		let prefix = match keywords.get("prefixItems") {
+			Some(Value::Array(schemas)) => schemas.len(),
+			_ => 0,
+		};
+		for (i, &item) in items.iter().enumerate() {
+			if i < prefix {
+				diagnostics.extend(self.validate(item, &format!("{}/{}", at("prefixItems"), i)));
+			} else if keywords.contains_key("items") {
+				diagnostics.extend(self.validate(item, &at("items")));
+			}
+		}
+		if keywords.contains_key("contains") {
+			let contained = items.iter().filter(|&&item| self.is_valid(item, &at("contains"))).count() as f64;
+			let min = keywords.get("minContains").and_then(Value::as_f64).unwrap_or(1.);
+			if contained < min {
+				diagnostics.push(self.diagnostic(node, format!("must contain at least {} matching items", min)));
+			}
+			if let Some(max) = keywords.get("maxContains").and_then(Value::as_f64) {
+				if contained > max {
+					diagnostics.push(self.diagnostic(node, format!("must contain at most {} matching items", max)));
+				}
+			}
+		}
+	}
+	fn check_object(&mut self, node: usize, entries: &[usize], keywords: &serde_json::Map<String, Value>, location: &str, diagnostics: &mut Vec<Diagnostic>) {
+		let buffer = self.buffer;
+		let at = |keyword: &str| format!("{}/{}", location, escape_token(keyword));
+		let members: Vec<(&str, usize, usize)> = entries.iter().filter_map(|&entry| match &buffer.nodes[entry].variant {
+			JsonVariant::ObjectEntry(key, value) => Some((key.as_str(), entry, *value)),
+			_ => None,
+		}).collect();
+		let has_key = |key: &str| members.iter().any(|&(k, _, _)| k==key);
+
+		let count = members.len() as f64;
+		if let Some(min) = keywords.get("minProperties").and_then(Value::as_f64) {
+			if count < min {
+				diagnostics.push(self.diagnostic(node, format!("must have at least {} properties", min)));
+			}
+		}
+		if let Some(max) = keywords.get("maxProperties").and_then(Value::as_f64) {
+			if count > max {
+				diagnostics.push(self.diagnostic(node, format!("must have at most {} properties", max)));
+			}
+		}
+		if let Some(Value::Array(required)) = keywords.get("required") {
+			for key in required.iter().filter_map(Value::as_str).filter(|key| !has_key(key)) {
+				diagnostics.push(self.diagnostic(node, format!("missing required property \"{}\"", key)));
+			}
+		}
+		if let Some(Value::Object(dependencies)) = keywords.get("dependentRequired") {
+			for (key, required) in dependencies.iter().filter(|(key, _)| has_key(key)) {
+				for other in required.as_array().into_iter().flatten().filter_map(Value::as_str).filter(|other| !has_key(other)) {
+					diagnostics.push(self.diagnostic(node, format!("\"{}\" requires property \"{}\"", key, other)));
+				}
+			}
+		}
+
+		let properties = keywords.get("properties").and_then(Value::as_object);
+		let patterns = keywords.get("patternProperties").and_then(Value::as_object);
+		for &(key, entry, value) in &members {
+			let mut evaluated = false;
+			if properties.is_some_and(|properties| properties.contains_key(key)) {
+				evaluated = true;
+				diagnostics.extend(self.validate(value, &format!("{}/{}", at("properties"), escape_token(key))));
+			}
+			for pattern in patterns.into_iter().flat_map(|patterns| patterns.keys()) {
+				if self.schema.matches(pattern, key) {
+					evaluated = true;
+					diagnostics.extend(self.validate(value, &format!("{}/{}", at("patternProperties"), escape_token(pattern))));
+				}
+			}
+			if !evaluated && keywords.contains_key("additionalProperties") {
+				if keywords.get("additionalProperties") == Some(&Value::Bool(false)) {
+					diagnostics.push(self.diagnostic(entry, format!("property \"{}\" isn't allowed", key)));
+				} else {
+					diagnostics.extend(self.validate(value, &at("additionalProperties")));
+				}
+			}
+			if let Some(Value::Object(names)) = keywords.get("propertyNames") {
+				let name = Value::String(key.to_string());
+				if !property_name_allowed(self.schema, names, &name) {
+					diagnostics.push(self.diagnostic(entry, format!("property name \"{}\" isn't allowed", key)));
+				}
+			}
+		}
+	}
+}
+
+// Property names aren't nodes, so only the string keywords are checked.
+fn property_name_allowed(schema: &Schema, keywords: &serde_json::Map<String, Value>, name: &Value) -> bool {
+	let text = name.as_str().unwrap_or("");
+	let length = text.chars().count() as f64;
+	keywords.get("minLength").and_then(Value::as_f64).is_none_or(|min| length >= min)
+		&& keywords.get("maxLength").and_then(Value::as_f64).is_none_or(|max| length <= max)
+		&& keywords.get("pattern").and_then(Value::as_str).is_none_or(|pattern| schema.matches(pattern, text))
+		&& keywords.get("const").is_none_or(|value| value == name)
+		&& keywords.get("enum").and_then(Value::as_array).is_none_or(|values| values.contains(name))
+}
+
+fn has_type(variant: &JsonVariant, name: &str) -> bool {
+	match (variant, name) {
+		(JsonVariant::Number(n), "integer") => n.is_integer(),
+		(variant, name) => type_name(variant) == name,
+	}
+}
+
+fn type_name(variant: &JsonVariant) -> &'static str {
+	match variant {
+		JsonVariant::Null => "null",
+		JsonVariant::Bool(_) => "boolean",
+		JsonVariant::Number(_) => "number",
+		JsonVariant::String(_) => "string",
+		JsonVariant::Array(_) => "array",
+		JsonVariant::Object(_) | JsonVariant::ObjectEntry(_, _) => "object",
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{JsonInput, PatchOperation};
+
+	fn buffer_with_schema(document: &str, schema: &str) -> (JsonBuffer, Rc<Schema>) {
+		let mut buffer = JsonBuffer::parse(document).unwrap();
+		let schema = Rc::new(Schema::parse(schema).unwrap());
+		buffer.set_schema(Some(schema.clone()));
+		(buffer, schema)
+	}
+
+	// Checks the diagnostics kept up to date across edits against those from
+	// validating the whole document afresh.
+	fn assert_fresh(buffer: &mut JsonBuffer, schema: &Rc<Schema>) {
+		let incremental = buffer.diagnostics().to_vec();
+		buffer.set_schema(Some(schema.clone()));
+		assert_eq!(incremental, buffer.diagnostics().to_vec());
+	}
+
+	#[test]
+	fn members_with_the_same_key_are_invalidated_separately() {
+		let (mut buffer, schema) = buffer_with_schema(r#"{"": null}"#, r#"{"additionalProperties": {"type": "null"}}"#);
+		assert!(buffer.diagnostics().is_empty());
+		let original = buffer.resolve("/").unwrap();
+		buffer.new_first_child();
+		assert!(buffer.diagnostics().is_empty());
+		buffer.selections = vec![original];
+		buffer.stringify();
+		assert!(!buffer.diagnostics().is_empty());
+		assert_fresh(&mut buffer, &schema);
+	}
+
+	#[test]
+	fn edits_keep_diagnostics_up_to_date() {
+		let schema = r#"{
+			"type": "object",
+			"required": ["id"],
+			"properties": {
+				"id": {"type": "integer"},
+				"tags": {"type": "array", "items": {"type": "string"}, "uniqueItems": true, "maxItems": 3},
+				"pair": {"prefixItems": [{"type": "string"}, {"type": "number"}]}
+			},
+			"additionalProperties": false
+		}"#;
+		let (mut buffer, schema) = buffer_with_schema(r#"{"id": 1, "tags": ["b", "a"], "pair": [1, "x"]}"#, schema);
+		assert_eq!(buffer.diagnostics().len(), 2);
+
+		// Renaming a key, one keystroke at a time.
+		buffer.selections = vec![buffer.children(0)[0]];
+		buffer.input(JsonInput::Char('x'));
+		assert_fresh(&mut buffer, &schema);
+		buffer.input(JsonInput::Backspace);
+		assert_fresh(&mut buffer, &schema);
+
+		// Reordering array items, which changes what prefixItems applies to.
+		buffer.selections = vec![buffer.resolve("/pair").unwrap()];
+		buffer.sort();
+		assert_fresh(&mut buffer, &schema);
+
+		// Adding and removing items.
+		buffer.selections = vec![buffer.resolve("/tags").unwrap()];
+		buffer.new_first_child();
+		buffer.stringify();
+		buffer.input(JsonInput::Char('a'));
+		assert_fresh(&mut buffer, &schema);
+		buffer.new_down_sibling();
+		assert_fresh(&mut buffer, &schema);
+		buffer.delete();
+		assert_fresh(&mut buffer, &schema);
+
+		// Patches, including a move from one container to another.
+		let patch = vec![
+			PatchOperation::Move { from: "/tags/0".to_string(), path: "/pair/0".to_string() },
+			PatchOperation::Replace { path: "/id".to_string(), value: serde_json::json!("one") },
+			PatchOperation::Add { path: "/extra".to_string(), value: serde_json::json!([]) },
+		];
+		buffer.apply_patch(&patch).unwrap();
+		assert_fresh(&mut buffer, &schema);
+		buffer.apply_merge_patch(&serde_json::json!({"extra": null, "id": 2}));
+		assert_fresh(&mut buffer, &schema);
+
+		// Replacing the root.
+		buffer.selections = vec![0];
+		buffer.delete();
+		assert_fresh(&mut buffer, &schema);
+	}
+
+	#[test]
+	fn unique_items_compares_numbers_by_value() {
+		let schema = r#"{"uniqueItems": true}"#;
+		let (buffer, _) = buffer_with_schema("[18446744073709551616, 18446744073709551617]", schema);
+		assert!(buffer.diagnostics().is_empty());
+		let (buffer, _) = buffer_with_schema("[1, 2, 1.0, 1e0]", schema);
+		assert_eq!(buffer.diagnostics().len(), 2);
+	}
+}
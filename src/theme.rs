@@ -0,0 +1,171 @@
+use std::fs;
+use std::path::Path;
+
+use skulpin::skia_safe::Color4f;
+
+// The largest font, line or character size a theme may set, so at least a
+// couple of lines fit in the window.
+const MAX_SIZE: f32 = 200.;
+
+pub struct Theme {
+	pub background: Color4f,
+	pub key: Color4f,
+	pub string: Color4f,
+	pub number: Color4f,
+	pub boolean: Color4f,
+	pub null: Color4f,
+	pub punctuation: Color4f,
+	pub status_background: Color4f,
+	pub status_text: Color4f,
+	pub error: Color4f,
+	pub selection: Color4f,
+	pub primary_selection: Color4f,
+	// Highlights behind the nodes a diff reports, drawn as filled boxes.
+	pub added: Color4f,
+	pub removed: Color4f,
+	pub changed: Color4f,
+	pub moved: Color4f,
+	// Highlight behind the nodes a merge left in conflict.
+	pub conflict: Color4f,
+	pub selection_width: f32,
+	// Filled selections are drawn as solid boxes behind the text instead of
+	// outlines, so they want translucent colours.
+	pub selection_fill: bool,
+	pub font_size: f32,
+	pub line_height: f32,
+	pub character_width: f32,
+	pub indent_width: usize,
+}
+
+impl Default for Theme {
+	fn default() -> Theme {
+		Theme {
+			background: Color4f::new(0., 0., 0., 1.),
+			key: Color4f::new(0.55, 0.75, 1.0, 1.),
+			string: Color4f::new(0.6, 0.9, 0.5, 1.),
+			number: Color4f::new(0.95, 0.65, 0.35, 1.),
+			boolean: Color4f::new(0.8, 0.55, 0.95, 1.),
+			null: Color4f::new(0.6, 0.6, 0.6, 1.),
+			punctuation: Color4f::new(1., 1., 1., 1.),
+			status_background: Color4f::new(0.2, 0.2, 0.2, 1.),
+			status_text: Color4f::new(1., 1., 1., 1.),
+			error: Color4f::new(1., 0.4, 0.4, 1.),
+			selection: Color4f::new(1., 0., 0., 1.),
+			primary_selection: Color4f::new(1., 0.8, 0., 1.),
+			added: Color4f::new(0.2, 0.8, 0.2, 0.3),
+			removed: Color4f::new(1., 0.27, 0.27, 0.3),
+			changed: Color4f::new(0.9, 0.72, 0., 0.3),
+			moved: Color4f::new(0.27, 0.53, 1., 0.3),
+			conflict: Color4f::new(1., 0.3, 0.9, 0.35),
+			selection_width: 1.,
+			selection_fill: false,
+			font_size: 18.,
+			line_height: 18.,
+			character_width: 9.,
+			indent_width: 2,
+		}
+	}
+}
+
+impl Theme {
+	pub fn load(path: &Path) -> Result<Theme, String> {
+		let text = fs::read_to_string(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+		Theme::parse(&text).map_err(|err| format!("{}: {}", path.display(), err))
+	}
+	// Anything the file leaves out keeps its default value.
+	pub fn parse(text: &str) -> Result<Theme, String> {
+		let value: toml::Value = text.parse().map_err(|err: toml::de::Error| err.to_string())?;
+		let mut theme = Theme::default();
+		read_size(&value, "font_size", &mut theme.font_size)?;
+		theme.line_height = theme.font_size;
+		read_size(&value, "line_height", &mut theme.line_height)?;
+		theme.character_width = theme.font_size / 2.;
+		read_size(&value, "character_width", &mut theme.character_width)?;
+		if let Some(indent_width) = value.get("indent_width") {
+			theme.indent_width = indent_width.as_integer()
+				.filter(|&width| width >= 0)
+				.ok_or("indent_width must be a non-negative integer")? as usize;
+		}
+		if let Some(palette) = value.get("palette") {
+			read_color(palette, "background", &mut theme.background)?;
+			read_color(palette, "key", &mut theme.key)?;
+			read_color(palette, "string", &mut theme.string)?;
+			read_color(palette, "number", &mut theme.number)?;
+			read_color(palette, "boolean", &mut theme.boolean)?;
+			read_color(palette, "null", &mut theme.null)?;
+			read_color(palette, "punctuation", &mut theme.punctuation)?;
+			read_color(palette, "status_background", &mut theme.status_background)?;
+			read_color(palette, "status_text", &mut theme.status_text)?;
+			read_color(palette, "error", &mut theme.error)?;
+		}
+		if let Some(selection) = value.get("selection") {
+			read_color(selection, "color", &mut theme.selection)?;
+			read_color(selection, "primary_color", &mut theme.primary_selection)?;
+			read_number(selection, "width", &mut theme.selection_width)?;
+			if let Some(fill) = selection.get("fill") {
+				theme.selection_fill = fill.as_bool().ok_or("selection.fill must be true or false")?;
+			}
+		}
+		if let Some(diff) = value.get("diff") {
+			read_color(diff, "added", &mut theme.added)?;
+			read_color(diff, "removed", &mut theme.removed)?;
+			read_color(diff, "changed", &mut theme.changed)?;
+			read_color(diff, "moved", &mut theme.moved)?;
+		}
+		if let Some(merge) = value.get("merge") {
+			read_color(merge, "conflict", &mut theme.conflict)?;
+		}
+		Ok(theme)
+	}
+}
+
+fn read_number(table: &toml::Value, key: &str, into: &mut f32) -> Result<(), String> {
+	match table.get(key) {
+		Some(toml::Value::Float(f)) => *into = *f as f32,
+		Some(toml::Value::Integer(i)) => *into = *i as f32,
+		Some(_) => return Err(format!("{} must be a number", key)),
+		None => {},
+	}
+	Ok(())
+}
+
+// A size in pixels, which the layout divides by.
+fn read_size(table: &toml::Value, key: &str, into: &mut f32) -> Result<(), String> {
+	read_number(table, key, into)?;
+	if *into > 0. && *into <= MAX_SIZE {
+		Ok(())
+	} else {
+		Err(format!("{} must be more than 0 and at most {}", key, MAX_SIZE))
+	}
+}
+
+// Colours are written "#rrggbb" or "#rrggbbaa".
+fn read_color(table: &toml::Value, key: &str, into: &mut Color4f) -> Result<(), String> {
+	if let Some(value) = table.get(key) {
+		let hex = value.as_str()
+			.and_then(|s| s.strip_prefix('#'))
+			.filter(|hex| (hex.len() == 6 || hex.len() == 8) && hex.is_ascii())
+			.ok_or_else(|| format!("{} must be a colour like \"#rrggbb\"", key))?;
+		let mut channels = [1.; 4];
+		for (i, channel) in channels.iter_mut().enumerate().take(hex.len() / 2) {
+			let byte = u8::from_str_radix(&hex[i*2..i*2+2], 16)
+				.map_err(|_| format!("{} must be a colour like \"#rrggbb\"", key))?;
+			*channel = byte as f32 / 255.;
+		}
+		*into = Color4f::new(channels[0], channels[1], channels[2], channels[3]);
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn sizes_must_be_positive_and_bounded() {
+		assert!(Theme::parse("line_height = 0").is_err());
+		assert!(Theme::parse("font_size = -3.5").is_err());
+		assert!(Theme::parse("line_height = 601").is_err());
+		assert_eq!(Theme::parse("font_size = 20").unwrap().line_height, 20.);
+	}
+}